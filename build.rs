@@ -0,0 +1,42 @@
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+// NOTE(jpg): src/llvm.rs declares the DIBuilder functions and `LLVMCreateTargetDataLayout` by hand
+// with the signatures of LLVM 14; llvm-sys 38 accepts any LLVM since 3.8, so an older LLVM would
+// link against different signatures instead of failing the build.
+const MIN_LLVM_MAJOR: u32 = 14;
+
+fn main() {
+    let version = llvm_config_version().unwrap_or_else(|| {
+        panic!("could not run llvm-config to check for LLVM {} or newer", MIN_LLVM_MAJOR)
+    });
+
+    let major = version.split('.').next().and_then(|major| major.parse::<u32>().ok());
+    match major {
+        Some(major) if major >= MIN_LLVM_MAJOR => {}
+        _ => panic!("found LLVM {}, need LLVM {} or newer", version, MIN_LLVM_MAJOR),
+    }
+
+    println!("cargo:rerun-if-env-changed=PATH");
+    println!("cargo:rerun-if-env-changed=LLVM_SYS_38_PREFIX");
+    println!("cargo:rerun-if-changed=build.rs");
+}
+
+/// Version of the LLVM llvm-sys links against; llvm-sys 38 uses the llvm-config from `PATH`,
+/// or else the one in `$LLVM_SYS_38_PREFIX/bin`.
+fn llvm_config_version() -> Option<String> {
+    let mut candidates = vec![PathBuf::from("llvm-config")];
+    if let Some(prefix) = env::var_os("LLVM_SYS_38_PREFIX") {
+        candidates.push(PathBuf::from(prefix).join("bin").join("llvm-config"));
+    }
+
+    candidates.iter().filter_map(|llvm_config| {
+        let output = Command::new(llvm_config).arg("--version").output().ok()?;
+        if output.status.success() {
+            String::from_utf8(output.stdout).ok().map(|version| version.trim().to_owned())
+        } else {
+            None
+        }
+    }).next()
+}
@@ -1,151 +1,1016 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::mem;
 use std::str::Chars;
+use std::vec;
 use self::BfInstruction::*;
 
 pub struct BfMachine {
     pub cache_size: i64,
+    /// Width of a single memory cell in bits; one of 8, 16, 32 or 64
+    pub cell_bits: u32,
     pub instructions: InstructionList,
     pub memory_overflow: MemoryOverflowBehaviour,
+    pub eof: EofBehaviour,
+    /// File descriptor `DebugLog` writes its `trace::TraceRecord` to
+    pub trace_fd: i32,
 }
 
+impl BfMachine {
+    /// Largest index 'grow' memory checks grow the memory for; twice the index in bytes has to
+    /// fit into the 32 bit sizes of the generated code.
+    pub fn max_grow_index(&self) -> u32 {
+        i32::MAX as u32 / (2 * (self.cell_bits / 8))
+    }
+}
+
+/// Instructions accessing memory carry an offset relative to the current pointer as their first
+/// value, e.g. `AddValue(offset, value)` means `ptr[offset] += value`.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum BfInstruction {
-    SetValue(i8),
-    AddValue(i8),
+    SetValue(i64, i64),
+    AddValue(i64, i64),
     AddPointer(i64),
-    Input,
-    Output,
+    /// `ptr[offset] += *ptr * factor`; leaves the current cell unchanged
+    MulAdd { offset: i64, factor: i64 },
+    /// `while(*ptr) ptr += stride;`
+    ScanZero(i64),
+    Input(i64),
+    Output(i64),
     BeginLoop,
     EndLoop,
     DebugLog,
 }
 
-//TODO(jpg): add 'resize memory'
+/// Tree form of an instruction list; loops own their body instead of being delimited by
+/// `BeginLoop` and `EndLoop`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    /// Any instruction except `BeginLoop` and `EndLoop`
+    Insn(BfInstruction),
+    Loop(Vec<Node>),
+}
+
+impl Node {
+    /// Builds the tree form of `list`; `None` if its loops are not balanced.
+    pub fn from_insns(list: &[BfInstruction]) -> Option<Vec<Node>> {
+        // NOTE(jpg): the bottom of the stack holds the top level nodes
+        let mut stack = vec![Vec::new()];
+
+        for insn in list {
+            match *insn {
+                BeginLoop => stack.push(Vec::new()),
+                EndLoop => {
+                    if stack.len() < 2 {
+                        return None;
+                    }
+                    let body = stack.pop().unwrap();
+                    stack.last_mut().unwrap().push(Node::Loop(body));
+                }
+                _ => stack.last_mut().unwrap().push(Node::Insn(*insn)),
+            }
+        }
+
+        if stack.len() == 1 { stack.pop() } else { None }
+    }
+
+    /// Flattens `nodes` back into instructions delimiting loops by `BeginLoop` and `EndLoop`.
+    pub fn to_insns(nodes: &[Node]) -> Vec<BfInstruction> {
+        let mut list = Vec::new();
+        Node::flatten(nodes, &mut list);
+        list
+    }
+
+    fn flatten(nodes: &[Node], list: &mut Vec<BfInstruction>) {
+        for node in nodes {
+            match *node {
+                Node::Insn(insn) => list.push(insn),
+                Node::Loop(ref body) => {
+                    list.push(BeginLoop);
+                    Node::flatten(body, list);
+                    list.push(EndLoop);
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum MemoryOverflowBehaviour {
     Undefined,
     Wrap,
     Abort,
+    Grow,
+}
+
+/// 1-based position of a character in the source code.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SourcePosition {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Renders the source line at `position` with a caret below the column; `None` if `source` has
+/// no such line.
+///
+/// ```text
+///  2 | ++[>+
+///    |   ^
+/// ```
+pub fn source_snippet(source: &str, position: SourcePosition) -> Option<String> {
+    let line = source.lines().nth(position.line.checked_sub(1)?)?;
+    let line_number = position.line.to_string();
+    let gutter = " ".repeat(line_number.len());
+
+    // NOTE(jpg): keep tabs so the caret lines up with the snippet
+    let indent: String = line.chars()
+        .take(position.column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    Some(format!(" {} | {}\n {} | {}^", line_number, line, gutter, indent))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UnmatchedBracket {
+    pub bracket: char,
+    pub position: SourcePosition,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// All unmatched brackets, ordered by their position in the source code
+    pub unmatched_brackets: Vec<UnmatchedBracket>,
 }
 
+/// Error of `InstructionList::from_vec`: a `BeginLoop` or `EndLoop` has no matching partner
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UnbalancedLoops;
+
+/// Error in textual brainfuck IR, see `InstructionList::from_ir`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IrParseError {
+    pub message: String,
+    pub position: SourcePosition,
+}
+
+impl IrParseError {
+    fn new<S: Into<String>>(message: S, position: SourcePosition) -> Self {
+        IrParseError {
+            message: message.into(),
+            position,
+        }
+    }
+}
+
+/// Value stored by `Input` when the end of input is reached
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EofBehaviour {
+    Unchanged,
+    Zero,
+    MinusOne,
+}
+
+/// Where `InstructionList::insert_debug_logs` inserts `DebugLog` instructions
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DebugPlacement {
+    /// Before every instruction and at the end of the program
+    All,
+    /// Before every loop and at the start of every iteration
+    Loops,
+    /// Before every output and after every input
+    Io,
+    /// Nowhere, only `#` breakpoints in the source code log
+    Breakpoints,
+}
+
+derive_FromStr!(MemoryOverflowBehaviour, {
+	MemoryOverflowBehaviour::Undefined: "undefined",
+	MemoryOverflowBehaviour::Wrap: "wrap",
+	MemoryOverflowBehaviour::Abort: "abort",
+	MemoryOverflowBehaviour::Grow: "grow"
+});
+
+derive_FromStr!(EofBehaviour, {
+	EofBehaviour::Unchanged: "unchanged",
+	EofBehaviour::Zero: "zero",
+	EofBehaviour::MinusOne: "minus-one"
+});
+
+derive_FromStr!(DebugPlacement, {
+	DebugPlacement::All: "all",
+	DebugPlacement::Loops: "loops",
+	DebugPlacement::Io: "io",
+	DebugPlacement::Breakpoints: "breakpoints"
+});
+
+/// Instructions with balanced loops; the fields are private to keep them that way.
 pub struct InstructionList {
-    pub list: Vec<BfInstruction>,
-    loop_comment_depth: u32,
+    pub(crate) list: Vec<BfInstruction>,
+    /// Source position of every instruction, used for debug info; empty if unknown
+    pub(crate) positions: Vec<SourcePosition>,
+}
+
+impl Default for InstructionList {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl InstructionList {
     pub fn new() -> Self {
         InstructionList {
             list: Vec::new(),
-            loop_comment_depth: 0,
+            positions: Vec::new(),
         }
     }
 
-    #[allow(dead_code)] // NOTE(jpg): currently only used by test code
-    pub fn from_vec(input: Vec<BfInstruction>) -> Self {
-        let mut list = Self::new();
-        for insn in input {
-            list.push(insn);
+    /// Wraps instructions without source positions; fails if the loops are not balanced.
+    pub fn from_vec(input: Vec<BfInstruction>) -> Result<Self, UnbalancedLoops> {
+        if Node::from_insns(&input).is_none() {
+            return Err(UnbalancedLoops);
         }
-        list
+
+        Ok(InstructionList {
+            list: input,
+            positions: Vec::new(),
+        })
     }
 
-    pub fn from_chars(input: Chars) -> Self {
+    pub fn from_chars(input: Chars) -> Result<Self, ParseError> {
+        Self::parse_chars(input, false)
+    }
+
+    /// Like `from_chars`, but parses the breakpoint character `#` as `DebugLog`.
+    pub fn from_chars_with_breakpoints(input: Chars) -> Result<Self, ParseError> {
+        Self::parse_chars(input, true)
+    }
+
+    fn parse_chars(input: Chars, breakpoints: bool) -> Result<Self, ParseError> {
         let mut result = InstructionList::new();
+        let mut unmatched_brackets = Vec::new();
+        let mut open_brackets = Vec::new();
+        let mut position = SourcePosition { line: 1, column: 1 };
+
         for c in input.fuse() {
+            let insn_position = position;
+            if c == '[' {
+                open_brackets.push(position);
+            } else if c == ']' && open_brackets.pop().is_none() {
+                unmatched_brackets.push(UnmatchedBracket {
+                    bracket: c,
+                    position,
+                });
+            }
+
+            if c == '\n' {
+                position.line += 1;
+                position.column = 1;
+            } else {
+                position.column += 1;
+            }
+
             let insn = match c {
-                '-' => Some(AddValue(-1)),
-                '+' => Some(AddValue(1)),
+                '-' => Some(AddValue(0, -1)),
+                '+' => Some(AddValue(0, 1)),
                 '<' => Some(AddPointer(-1)),
                 '>' => Some(AddPointer(1)),
-                ',' => Some(Input),
-                '.' => Some(Output),
+                ',' => Some(Input(0)),
+                '.' => Some(Output(0)),
                 '[' => Some(BeginLoop),
                 ']' => Some(EndLoop),
+                '#' if breakpoints => Some(DebugLog),
                 _ => None,
             };
             if let Some(insn) = insn {
-                result.push(insn);
+                result.push_at(insn, insn_position);
+            }
+        }
+
+        if open_brackets.is_empty() && unmatched_brackets.is_empty() {
+            return Ok(result);
+        }
+
+        unmatched_brackets.extend(open_brackets.into_iter().map(|position| {
+            UnmatchedBracket {
+                bracket: '[',
+                position,
+            }
+        }));
+        unmatched_brackets.sort_by_key(|b| (b.position.line, b.position.column));
+
+        Err(ParseError { unmatched_brackets })
+    }
+
+    /// Parses the textual brainfuck IR written by the `Display` implementation.
+    ///
+    /// One instruction per line (line breaks are optional), `#` starts a comment:
+    ///
+    /// ```text
+    /// set 0         # *ptr = 0
+    /// add @-1 3     # ptr[-1] += 3
+    /// ptr 2         # ptr += 2
+    /// muladd @1 2   # ptr[1] += *ptr * 2
+    /// scan -1       # while(*ptr) ptr -= 1;
+    /// in @1         # ptr[1] = getchar()
+    /// out           # putchar(*ptr)
+    /// loop {        # while(*ptr) {
+    ///     debug     #     trace()
+    /// }             # }
+    /// ```
+    pub fn from_ir(input: &str) -> Result<Self, IrParseError> {
+        let mut result = InstructionList::new();
+        let mut open_loops = Vec::new();
+        let mut tokens = ir_tokens(input).into_iter().peekable();
+
+        while let Some((token, position)) = tokens.next() {
+            let insn = match token {
+                "set" => {
+                    let offset = ir_offset(&mut tokens)?;
+                    SetValue(offset, ir_integer(&mut tokens, token, position)?)
+                }
+                "add" => {
+                    let offset = ir_offset(&mut tokens)?;
+                    AddValue(offset, ir_integer(&mut tokens, token, position)?)
+                }
+                "ptr" => AddPointer(ir_integer(&mut tokens, token, position)?),
+                "muladd" => {
+                    let offset = ir_offset(&mut tokens)?;
+                    let factor = ir_integer(&mut tokens, token, position)?;
+                    MulAdd { offset, factor }
+                }
+                "scan" => ScanZero(ir_integer(&mut tokens, token, position)?),
+                "in" => Input(ir_offset(&mut tokens)?),
+                "out" => Output(ir_offset(&mut tokens)?),
+                "debug" => DebugLog,
+                "loop" => {
+                    match tokens.next() {
+                        Some(("{", _)) => {}
+                        _ => return Err(IrParseError::new("expected '{' after 'loop'", position)),
+                    }
+                    open_loops.push(position);
+                    BeginLoop
+                }
+                "}" => {
+                    if open_loops.pop().is_none() {
+                        return Err(IrParseError::new("unmatched '}'", position));
+                    }
+                    EndLoop
+                }
+                _ => {
+                    let message = format!("unknown instruction '{}'", token);
+                    return Err(IrParseError::new(message, position));
+                }
+            };
+            result.push_at(insn, position);
+        }
+
+        match open_loops.pop() {
+            Some(position) => Err(IrParseError::new("unclosed 'loop'", position)),
+            None => Ok(result),
+        }
+    }
+
+    pub fn instructions(&self) -> &[BfInstruction] {
+        &self.list
+    }
+
+    pub(crate) fn push(&mut self, insn: BfInstruction) {
+        self.list.push(insn);
+    }
+
+    /// Pushes an instruction parsed at `position` in the source code.
+    pub(crate) fn push_at(&mut self, insn: BfInstruction, position: SourcePosition) {
+        self.list.push(insn);
+        self.positions.push(position);
+    }
+
+    /// Source position of the instruction at `index`, if the positions are known.
+    pub fn position(&self, index: usize) -> Option<SourcePosition> {
+        if self.positions.len() == self.list.len() {
+            self.positions.get(index).cloned()
+        } else {
+            None
+        }
+    }
+
+    /// Inserts `DebugLog` instructions as selected by `placement`; they share the source
+    /// position of the instruction they belong to.
+    pub fn insert_debug_logs(&mut self, placement: DebugPlacement) {
+        let mut result = InstructionList::new();
+
+        for (i, &insn) in self.list.iter().enumerate() {
+            let (before, after) = match (placement, insn) {
+                (DebugPlacement::All, DebugLog) => (false, false),
+                (DebugPlacement::All, _) => (true, false),
+                (DebugPlacement::Loops, BeginLoop) => (true, true),
+                (DebugPlacement::Loops, ScanZero(_)) => (true, false),
+                (DebugPlacement::Io, Output(_)) => (true, false),
+                (DebugPlacement::Io, Input(_)) => (false, true),
+                _ => (false, false),
+            };
+
+            let position = self.position(i);
+            if before {
+                result.push_debug_log(position);
+            }
+            result.push_maybe_at(insn, position);
+            if after {
+                result.push_debug_log(position);
+            }
+        }
+
+        if placement == DebugPlacement::All {
+            let position = self.list.len().checked_sub(1).and_then(|i| self.position(i));
+            result.push_debug_log(position);
+        }
+
+        *self = result;
+    }
+
+    /// Pushes a `DebugLog`, unless the list already ends with one.
+    fn push_debug_log(&mut self, position: Option<SourcePosition>) {
+        if self.list.last() != Some(&DebugLog) {
+            self.push_maybe_at(DebugLog, position);
+        }
+    }
+
+    fn push_maybe_at(&mut self, insn: BfInstruction, position: Option<SourcePosition>) {
+        match position {
+            Some(position) => self.push_at(insn, position),
+            None => self.push(insn),
+        }
+    }
+}
+
+impl fmt::Display for BfInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SetValue(offset, value) => write!(f, "set{} {}", IrOffset(offset), value),
+            AddValue(offset, value) => write!(f, "add{} {}", IrOffset(offset), value),
+            AddPointer(value) => write!(f, "ptr {}", value),
+            MulAdd { offset, factor } => write!(f, "muladd{} {}", IrOffset(offset), factor),
+            ScanZero(stride) => write!(f, "scan {}", stride),
+            Input(offset) => write!(f, "in{}", IrOffset(offset)),
+            Output(offset) => write!(f, "out{}", IrOffset(offset)),
+            BeginLoop => write!(f, "loop {{"),
+            EndLoop => write!(f, "}}"),
+            DebugLog => write!(f, "debug"),
+        }
+    }
+}
+
+/// Writes the textual brainfuck IR, see `InstructionList::from_ir`
+impl fmt::Display for InstructionList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut depth = 0usize;
+        for insn in &self.list {
+            if *insn == EndLoop {
+                depth = depth.saturating_sub(1);
+            }
+            writeln!(f, "{}{}", "    ".repeat(depth), insn)?;
+            if *insn == BeginLoop {
+                depth += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Memory offset of an instruction in textual IR; omitted if zero
+struct IrOffset(i64);
+impl fmt::Display for IrOffset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 == 0 {
+            Ok(())
+        } else {
+            write!(f, " @{}", self.0)
+        }
+    }
+}
+
+type IrTokens<'a> = Peekable<vec::IntoIter<(&'a str, SourcePosition)>>;
+
+/// Splits textual IR at whitespace and braces, dropping comments.
+fn ir_tokens(input: &str) -> Vec<(&str, SourcePosition)> {
+    let mut tokens = Vec::new();
+
+    for (line_index, line) in input.lines().enumerate() {
+        let line = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        let position = |column: usize| {
+            SourcePosition {
+                line: line_index + 1,
+                column: column + 1,
+            }
+        };
+
+        // NOTE(jpg): byte index and column of the current token
+        let mut start: Option<(usize, usize)> = None;
+        for (column, (i, c)) in line.char_indices().enumerate() {
+            let is_brace = c == '{' || c == '}';
+            if c.is_whitespace() || is_brace {
+                if let Some((begin, begin_column)) = start.take() {
+                    tokens.push((&line[begin..i], position(begin_column)));
+                }
+                if is_brace {
+                    tokens.push((&line[i..i + 1], position(column)));
+                }
+            } else if start.is_none() {
+                start = Some((i, column));
+            }
+        }
+        if let Some((begin, begin_column)) = start {
+            tokens.push((&line[begin..], position(begin_column)));
+        }
+    }
+
+    tokens
+}
+
+/// Parses an optional `@offset` operand.
+fn ir_offset(tokens: &mut IrTokens) -> Result<i64, IrParseError> {
+    let (token, position) = match tokens.peek() {
+        Some(&(token, position)) if token.starts_with('@') => (token, position),
+        _ => return Ok(0),
+    };
+    tokens.next();
+
+    token[1..].parse().map_err(|_| {
+        IrParseError::new(format!("invalid offset '{}'", token), position)
+    })
+}
+
+fn ir_integer(
+    tokens: &mut IrTokens,
+    insn: &str,
+    insn_position: SourcePosition,
+) -> Result<i64, IrParseError> {
+    match tokens.next() {
+        Some((token, position)) => token.parse().map_err(|_| {
+            IrParseError::new(format!("invalid integer '{}'", token), position)
+        }),
+        None => {
+            let message = format!("expected integer after '{}'", insn);
+            Err(IrParseError::new(message, insn_position))
+        }
+    }
+}
+
+/// An optimization pass rewriting the instruction list
+pub trait Pass {
+    /// Name used to select the pass on the command line
+    fn name(&self) -> &'static str;
+    fn run(&self, insns: &mut InstructionList);
+}
+
+/// Names of all passes in the order they are run by `PassManager::with_opt_level(3)`
+pub const PASS_NAMES: [&str; 8] = [
+    "fold-values",
+    "fold-pointer",
+    "clear-loop",
+    "scan-loop",
+    "mul-add-loop",
+    "known-zero",
+    "dead-loop",
+    "sink-pointer",
+];
+
+pub fn create_pass(name: &str) -> Option<Box<dyn Pass>> {
+    let pass: Box<dyn Pass> = match name {
+        "fold-values" => Box::new(FoldValues),
+        "fold-pointer" => Box::new(FoldPointer),
+        "clear-loop" => Box::new(ClearLoop),
+        "scan-loop" => Box::new(ScanLoop),
+        "mul-add-loop" => Box::new(MulAddLoop),
+        "known-zero" => Box::new(KnownZero),
+        "dead-loop" => Box::new(DeadLoop),
+        "sink-pointer" => Box::new(SinkPointer),
+        _ => return None,
+    };
+    Some(pass)
+}
+
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl Default for PassManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PassManager {
+    pub fn new() -> Self {
+        PassManager { passes: Vec::new() }
+    }
+
+    /// Optimization levels:
+    /// 0: no passes,
+    /// 1: folding of consecutive instructions,
+    /// 2: additionally loop rewrites,
+    /// 3: additionally offset addressing
+    pub fn with_opt_level(opt_level: u32) -> Self {
+        let count = match opt_level {
+            0 => 0,
+            1 => 2,
+            2 => 7,
+            _ => PASS_NAMES.len(),
+        };
+        Self::with_pass_names(&PASS_NAMES[..count]).unwrap()
+    }
+
+    pub fn with_pass_names<S: AsRef<str>>(names: &[S]) -> Result<Self, String> {
+        let mut manager = Self::new();
+        for name in names {
+            let pass = create_pass(name.as_ref()).ok_or_else(|| {
+                format!("unknown pass '{}'", name.as_ref())
+            })?;
+            manager.add(pass);
+        }
+        Ok(manager)
+    }
+
+    pub fn add(&mut self, pass: Box<dyn Pass>) {
+        self.passes.push(pass);
+    }
+
+    pub fn pass_names(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    /// Runs all passes in order until the instruction list does not change anymore.
+    ///
+    /// The passes keep the source positions; an instruction replacing several others gets the
+    /// position of the first of them, e.g. the `[` of a rewritten loop.
+    pub fn run(&self, insns: &mut InstructionList) {
+        // NOTE(jpg): passes never grow the list, the limit only guards against passes undoing
+        // each other
+        for _ in 0..16 {
+            let before = insns.list.clone();
+            for pass in &self.passes {
+                pass.run(insns);
+            }
+            if insns.list == before {
+                break;
             }
         }
-        return result;
+    }
+}
+
+/// Instruction list built by `rebuild`, keeping the source positions if they are known.
+///
+/// Pushed instructions get the position of the last instruction popped while handling the
+/// current instruction, otherwise the position of the current instruction.
+struct RebuildList {
+    list: Vec<BfInstruction>,
+    positions: Vec<SourcePosition>,
+    current: Option<SourcePosition>,
+    popped: Option<SourcePosition>,
+}
+
+impl RebuildList {
+    fn last(&self) -> Option<&BfInstruction> {
+        self.list.last()
     }
 
-    pub fn push(&mut self, insn: BfInstruction) {
+    fn pop(&mut self) {
+        self.list.pop();
+        if let Some(position) = self.positions.pop() {
+            self.popped = Some(position);
+        }
+    }
 
-        if self.loop_comment_depth != 0 {
-            match insn {
-                BeginLoop => self.loop_comment_depth += 1,
-                EndLoop => self.loop_comment_depth -= 1,
-                _ => {}
+    fn push(&mut self, insn: BfInstruction) {
+        self.list.push(insn);
+        if let Some(position) = self.popped.or(self.current) {
+            self.positions.push(position);
+        }
+    }
+}
+
+/// Pushes every instruction of `insns` through `push`, which may rewrite the end of the list.
+fn rebuild<F>(insns: &mut InstructionList, mut push: F)
+where
+    F: FnMut(&mut RebuildList, BfInstruction),
+{
+    let known_positions = insns.positions.len() == insns.list.len();
+    let positions = mem::take(&mut insns.positions);
+    let mut list = RebuildList {
+        list: Vec::new(),
+        positions: Vec::new(),
+        current: None,
+        popped: None,
+    };
+
+    for (i, insn) in mem::take(&mut insns.list).into_iter().enumerate() {
+        list.current = if known_positions { Some(positions[i]) } else { None };
+        list.popped = None;
+        push(&mut list, insn);
+    }
+
+    insns.list = list.list;
+    insns.positions = list.positions;
+}
+
+/// Replaces loops, innermost first, by the result of `rewrite` for their body; `None` keeps the
+/// loop. Lists with unbalanced loops are left unchanged.
+fn rewrite_loops<F>(insns: &mut InstructionList, rewrite: F)
+where
+    F: Fn(&[Node]) -> Option<Vec<Node>>,
+{
+    if Node::from_insns(&insns.list).is_none() {
+        return;
+    }
+
+    let mut loop_starts = Vec::new();
+    rebuild(insns, |list, insn| match insn {
+        BeginLoop => {
+            loop_starts.push(list.list.len());
+            list.push(insn);
+        }
+        EndLoop => {
+            let start = loop_starts.pop().unwrap();
+            let body = Node::from_insns(&list.list[start + 1..]).unwrap();
+            match rewrite(&body) {
+                Some(replacement) => {
+                    // NOTE(jpg): pops the `BeginLoop` last, so the replacement gets its position
+                    while list.list.len() > start {
+                        list.pop();
+                    }
+                    for insn in Node::to_insns(&replacement) {
+                        list.push(insn);
+                    }
+                }
+                None => list.push(insn),
             }
-            return;
         }
+        _ => list.push(insn),
+    });
+}
 
-        match (self.list.last(), insn) {
+struct FoldValues;
+impl Pass for FoldValues {
+    fn name(&self) -> &'static str {
+        "fold-values"
+    }
+
+    fn run(&self, insns: &mut InstructionList) {
+        rebuild(insns, |list, insn| match (list.last(), insn) {
 
             // value += 0; => <empty>
-            (_, AddValue(0)) => {
+            (_, AddValue(_, 0)) => {
                 // skip instruction
             }
 
             // value += a; value += b; => value += a + b;
-            (Some(&AddValue(value)), AddValue(other)) => {
-                self.list.pop();
-                self.push(AddValue(value.wrapping_add(other)));
+            (Some(&AddValue(offset, value)), AddValue(other_offset, other))
+                if offset == other_offset => {
+                list.pop();
+                let value = value.wrapping_add(other);
+                if value != 0 {
+                    list.push(AddValue(offset, value));
+                }
             }
 
             // value = a; value += b; => value = a + b;
-            (Some(&SetValue(value)), AddValue(other)) => {
-                self.list.pop();
-                self.push(SetValue(value.wrapping_add(other)));
+            (Some(&SetValue(offset, value)), AddValue(other_offset, other))
+                if offset == other_offset => {
+                list.pop();
+                list.push(SetValue(offset, value.wrapping_add(other)));
             }
 
             // value  = a; value = b; => value = b;
             // value += a; value = b; => value = b;
-            (Some(&SetValue(_)), SetValue(_)) |
-            (Some(&AddValue(_)), SetValue(_)) => {
-                self.list.pop();
-                self.push(insn);
+            (Some(&SetValue(offset, _)), SetValue(other_offset, _)) |
+            (Some(&AddValue(offset, _)), SetValue(other_offset, _))
+                if offset == other_offset => {
+                list.pop();
+                list.push(insn);
+            }
+
+            _ => list.push(insn),
+        });
+    }
+}
+
+struct FoldPointer;
+impl Pass for FoldPointer {
+    fn name(&self) -> &'static str {
+        "fold-pointer"
+    }
+
+    fn run(&self, insns: &mut InstructionList) {
+        rebuild(insns, |list, insn| match (list.last(), insn) {
+
+            // ptr += 0; => <empty>
+            (_, AddPointer(0)) => {
+                // skip instruction
             }
 
             // ptr += a; ptr += b; => ptr += a + b;
             (Some(&AddPointer(value)), AddPointer(other)) => {
-                self.list.pop();
-                self.push(AddPointer(value + other));
+                list.pop();
+                if value + other != 0 {
+                    list.push(AddPointer(value + other));
+                }
             }
 
+            _ => list.push(insn),
+        });
+    }
+}
+
+struct ClearLoop;
+impl Pass for ClearLoop {
+    fn name(&self) -> &'static str {
+        "clear-loop"
+    }
+
+    fn run(&self, insns: &mut InstructionList) {
+        rewrite_loops(insns, |body| match *body {
+
             // while(value) value--; => value = 0;
-            (Some(&AddValue(value)), EndLoop)
-                if value % 2 != 0 && self.list.get(self.list.len() - 2) == Some(&BeginLoop) => {
-                self.list.pop();
-                self.list.pop();
-                self.push(SetValue(0));
+            [Node::Insn(AddValue(0, value))] if value % 2 != 0 => {
+                Some(vec![Node::Insn(SetValue(0, 0))])
             }
 
+            _ => None,
+        });
+    }
+}
+
+struct ScanLoop;
+impl Pass for ScanLoop {
+    fn name(&self) -> &'static str {
+        "scan-loop"
+    }
+
+    fn run(&self, insns: &mut InstructionList) {
+        rewrite_loops(insns, |body| match *body {
+
+            // while(value) ptr += a; => scan_zero(a);
+            [Node::Insn(AddPointer(value))] => Some(vec![Node::Insn(ScanZero(value))]),
+
+            _ => None,
+        });
+    }
+}
+
+struct MulAddLoop;
+impl Pass for MulAddLoop {
+    fn name(&self) -> &'static str {
+        "mul-add-loop"
+    }
+
+    fn run(&self, insns: &mut InstructionList) {
+        // while(value) { value--; ptr[a] += x; ptr[b] += y; }
+        // => ptr[a] += value * x; ptr[b] += value * y; value = 0;
+        rewrite_loops(insns, MulAddLoop::replacement);
+    }
+}
+
+impl MulAddLoop {
+    /// Checks whether the loop body only adds multiples of the current cell to other cells, and
+    /// returns the replacing instructions.
+    fn replacement(body: &[Node]) -> Option<Vec<Node>> {
+        let mut offset = 0;
+        let mut factors: Vec<(i64, i64)> = Vec::new();
+        for node in body {
+            match *node {
+                Node::Insn(AddPointer(value)) => offset += value,
+                Node::Insn(AddValue(value_offset, value)) => {
+                    let value_offset = offset + value_offset;
+                    match factors.iter_mut().find(|&&mut (o, _)| o == value_offset) {
+                        Some(&mut (_, ref mut factor)) => *factor = factor.wrapping_add(value),
+                        None => factors.push((value_offset, value)),
+                    }
+                }
+                _ => return None,
+            }
+        }
+
+        let decrement = factors.iter().find(|&&(o, _)| o == 0).map(|&(_, f)| f);
+        if offset != 0 || decrement != Some(-1) {
+            return None;
+        }
+
+        let mut replacement: Vec<_> = factors
+            .into_iter()
+            .filter(|&(offset, factor)| offset != 0 && factor != 0)
+            .map(|(offset, factor)| Node::Insn(MulAdd { offset, factor }))
+            .collect();
+        replacement.push(Node::Insn(SetValue(0, 0)));
+
+        Some(replacement)
+    }
+}
+
+struct KnownZero;
+impl Pass for KnownZero {
+    fn name(&self) -> &'static str {
+        "known-zero"
+    }
+
+    fn run(&self, insns: &mut InstructionList) {
+        rebuild(insns, |list, insn| match (list.last(), insn) {
+
             // while(value != 0) { ... }; value += a; => while(value != 0) { ... }; value = a;
-            (Some(&EndLoop), AddValue(value)) => {
-                self.push(SetValue(value));
+            (Some(&EndLoop), AddValue(0, value)) |
+            (Some(&ScanZero(_)), AddValue(0, value)) => {
+                list.push(SetValue(0, value));
             }
 
             // while(value != 0) { ... }; value = 0; => while(value != 0) { ... };
-            (Some(&EndLoop), SetValue(0)) => {
+            (Some(&EndLoop), SetValue(0, 0)) |
+            (Some(&ScanZero(_)), SetValue(0, 0)) => {
                 // drop instruction
             }
 
-            // value = 0;           while(value) { ... } => value = 0;
-            // while(a) { stmt(); } while(a)     { ... } => while (a) { stmt(); }
-            (Some(&SetValue(0)), BeginLoop) |
-            (Some(&EndLoop), BeginLoop) => {
-                self.loop_comment_depth += 1;
+            _ => list.push(insn),
+        });
+    }
+}
+
+struct DeadLoop;
+impl Pass for DeadLoop {
+    fn name(&self) -> &'static str {
+        "dead-loop"
+    }
+
+    fn run(&self, insns: &mut InstructionList) {
+        let mut loop_comment_depth = 0;
+
+        rebuild(insns, |list, insn| {
+            if loop_comment_depth != 0 {
+                match insn {
+                    BeginLoop => loop_comment_depth += 1,
+                    EndLoop => loop_comment_depth -= 1,
+                    _ => {}
+                }
+                return;
+            }
+
+            match (list.last(), insn) {
+
+                // value = 0;           while(value) { ... } => value = 0;
+                // while(a) { stmt(); } while(a)     { ... } => while (a) { stmt(); }
+                (Some(&SetValue(0, 0)), BeginLoop) |
+                (Some(&EndLoop), BeginLoop) |
+                (Some(&ScanZero(_)), BeginLoop) => {
+                    loop_comment_depth += 1;
+                }
+
+                _ => list.push(insn),
             }
+        });
+    }
+}
 
-            _ => self.list.push(insn),
-        }
+/// Replaces the pointer moves inside a basic block with offsets of the instructions accessing
+/// memory, so the pointer only moves once at the end of the block.
+///
+/// `ptr++; *ptr += 1; ptr++; *ptr += 1; ptr -= 2;` => `ptr[1] += 1; ptr[2] += 1;`
+struct SinkPointer;
+impl Pass for SinkPointer {
+    fn name(&self) -> &'static str {
+        "sink-pointer"
     }
 
-    pub fn insert_debug_logs(&mut self) {
-        for i in 0..self.list.len() + 1 {
-            self.list.insert(i * 2, BfInstruction::DebugLog);
+    fn run(&self, insns: &mut InstructionList) {
+        let mut offset = 0;
+        // NOTE(jpg): the pointer move at the end belongs to the last instruction
+        let last_position = insns.list.len().checked_sub(1).and_then(|i| insns.position(i));
+
+        rebuild(insns, |list, insn| {
+            let insn = match insn {
+                AddPointer(value) => {
+                    offset += value;
+                    return;
+                }
+                SetValue(value_offset, value) => SetValue(offset + value_offset, value),
+                AddValue(value_offset, value) => AddValue(offset + value_offset, value),
+                Input(value_offset) => Input(offset + value_offset),
+                Output(value_offset) => Output(offset + value_offset),
+
+                // NOTE(jpg): these instructions depend on the actual pointer; the sunk pointer
+                // move gets their source position
+                MulAdd { .. } | ScanZero(_) | BeginLoop | EndLoop | DebugLog => {
+                    if offset != 0 {
+                        list.push(AddPointer(offset));
+                        offset = 0;
+                    }
+                    insn
+                }
+            };
+            list.push(insn);
+        });
+
+        if offset != 0 {
+            insns.push_maybe_at(AddPointer(offset), last_position);
         }
     }
 }
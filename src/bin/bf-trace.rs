@@ -0,0 +1,114 @@
+extern crate argparse;
+extern crate llvm_brainfuck;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+
+use argparse::{ArgumentParser, Store};
+
+use llvm_brainfuck::TraceRecord;
+
+struct Config {
+    input: String,
+    tape: i64,
+}
+
+fn main() {
+    let mut cfg = Config {
+        input: "".to_owned(),
+        tape: 0,
+    };
+
+    {
+        let mut parser = ArgumentParser::new();
+        parser.set_description(
+            "Pretty-prints the trace written by llvm-brainfuck -d, one line per record:
+            instruction index, source position, pointer and changed cells",
+        );
+        parser.refer(&mut cfg.input).add_option(
+            &["-i", "--input"],
+            Store,
+            "Trace file; stdin if not set or empty.",
+        );
+        parser.refer(&mut cfg.tape).add_option(
+            &["-t", "--tape"],
+            Store,
+            "Also print the cells up to this distance from the pointer",
+        );
+        parser.parse_args_or_exit();
+    }
+
+    if let Err(err) = run(&cfg) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run(cfg: &Config) -> Result<(), String> {
+    let input: Box<dyn BufRead> = if cfg.input.is_empty() {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        let file = fs::File::open(&cfg.input).map_err(|err| {
+            format!("failed to open '{}': {}", cfg.input, err)
+        })?;
+        Box::new(BufReader::new(file))
+    };
+
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+
+    // NOTE(jpg): records only contain changed cells, all others keep their last value
+    let mut tape = BTreeMap::new();
+
+    for (line_index, line) in input.lines().enumerate() {
+        let line = line.map_err(|err| format!("failed to read trace: {}", err))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = TraceRecord::from_json(&line).map_err(|err| {
+            format!("invalid trace record in line {}: {}", line_index + 1, err)
+        })?;
+
+        let position = match record.position {
+            Some(position) => format!("{}:{}", position.line, position.column),
+            None => "-".to_owned(),
+        };
+        let changes: Vec<_> = record
+            .cells
+            .iter()
+            .map(|&(index, value)| {
+                let old_value = tape.insert(index as i64, value).unwrap_or(0);
+                format!("[{}] {} -> {}", index, old_value, value)
+            })
+            .collect();
+
+        writeln!(
+            output,
+            "{:>7} {:>9}  ptr {:>5}  {}",
+            record.insn,
+            position,
+            record.pointer,
+            changes.join(", ")
+        ).map_err(|err| format!("failed to write output: {}", err))?;
+
+        if cfg.tape > 0 {
+            let pointer = record.pointer as i64;
+            let cells: Vec<_> = (pointer - cfg.tape..pointer + cfg.tape + 1)
+                .filter(|&index| index >= 0)
+                .map(|index| {
+                    let value = tape.get(&index).cloned().unwrap_or(0);
+                    if index == pointer {
+                        format!("[{}]", value)
+                    } else {
+                        value.to_string()
+                    }
+                })
+                .collect();
+            writeln!(output, "{:>17}  {}", "tape", cells.join(" "))
+                .map_err(|err| format!("failed to write output: {}", err))?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,42 +1,100 @@
+use std::env;
+use std::path::Path;
+
 use llvm::*;
 use llvm::sys::LLVMIntPredicate::*;
 
 use bf::*;
 use bf::MemoryOverflowBehaviour::*;
-
-pub fn compile(machine: &BfMachine, emit_main: bool) -> (Module, LLVMString) {
+use bf::EofBehaviour;
+
+/// Generates the function `brainfuck` and, if `emit_main` is set, a `main` function calling it.
+///
+/// With a `source_path` the module contains DWARF debug info mapping the instructions to their
+/// positions in that file, see `InstructionList::positions`.
+pub fn compile(
+    machine: &BfMachine,
+    emit_main: bool,
+    target: &Target,
+    source_path: Option<&str>,
+) -> (Module, LLVMString) {
 
     let function_name = llvm_str!(b"brainfuck\0");
     let module = Module::new(llvm_str!(b"brainfuck\0"));
-    module.set_default_target();
+    match target.triple {
+        Some(ref triple) => module.set_target(to_llvm_string(triple.as_str())),
+        None => module.set_default_target(),
+    }
+    // NOTE(jpg): unknown targets are reported when the module is emitted, until then LLVM's
+    // default data layout is good enough
+    let _ = module.set_data_layout(target);
+    let has_memrchr = module.get_target().is_some_and(|triple| has_memrchr(&triple));
 
     let int1 = module.i1_type;
+    let int8 = module.i8_type;
     let int32 = module.i32_type;
-    let value_type = module.i8_type;
+    let byte_ptr_type = int8.ptr_type();
+    let value_type = module.int_type(machine.cell_bits);
     let ptr_type = value_type.ptr_type();
 
-    let malloc = module.add_function(llvm_str!(b"malloc\0"), &mut [int32], ptr_type);
+    let malloc = module.add_function(llvm_str!(b"malloc\0"), &mut [int32], byte_ptr_type);
+    // NOTE(jpg): the alignment argument was dropped from the intrinsic in LLVM 7
     let memset = module.add_function(
         llvm_str!(b"llvm.memset.p0i8.i32\0"),
-        &mut [ptr_type, value_type, int32, int32, int1],
+        &mut [byte_ptr_type, int8, int32, int1],
         module.void_type,
     );
-    let free = module.add_function(llvm_str!(b"free\0"), &mut [ptr_type], module.void_type);
-    let putchar = module.add_function(llvm_str!(b"putchar\0"), &mut [value_type], value_type);
-    let getchar = module.add_function(llvm_str!(b"getchar\0"), &mut [], value_type);
-    let mut debug_log = None;
+    let free = module.add_function(llvm_str!(b"free\0"), &mut [byte_ptr_type], module.void_type);
+    let putchar = module.add_function(llvm_str!(b"putchar\0"), &mut [int32], int32);
+    let getchar = module.add_function(llvm_str!(b"getchar\0"), &mut [], int32);
+    let mut trace: Option<TraceContext> = None;
+    let mut grow_memory = None;
+    let mut memchr = None;
+    let mut memrchr = None;
 
     let function = module.add_function(function_name, &mut [], int32);
+
+    let debug_info = source_path.map(|source_path| {
+        let (file_name, directory) = split_source_path(source_path);
+        DebugInfoBuilder::new(&module, &file_name, &directory)
+    });
+    let debug_scope = debug_info.as_ref().map(|debug_info| {
+        debug_info.create_function(function, "brainfuck", 1)
+    });
+    let debug_location = |position: SourcePosition| match (debug_info.as_ref(), debug_scope) {
+        (Some(debug_info), Some(scope)) => {
+            Some(debug_info.location(scope, position.line as u32, position.column as u32))
+        }
+        _ => None,
+    };
+
+    // NOTE(jpg): setup code and instructions without known position belong to the start of the
+    // source code
+    module.set_debug_location(debug_location(SourcePosition { line: 1, column: 0 }));
+
     let mut bb = module.append_basic_block(function, llvm_str!(b"entry\0"));
     let mut builder = Builder::new(&module, bb);
 
     let zero_value = builder.sint(value_type, 0);
+    let zero_8 = builder.uint(int8, 0);
     let false_i1 = builder.uint(module.i1_type, 0);
     let one_32 = builder.uint(int32, 1);
+    let cell_bytes = machine.cell_bits / 8;
+
+    let initial_cache_size = builder.uint(int32, machine.cache_size as u64);
+    let initial_bytes = builder.uint(int32, machine.cache_size as u64 * cell_bytes as u64);
+    let initial_memory = builder.call(malloc, &mut [initial_bytes], llvm_str!(b"memory\0"));
+    builder.call(memset, &mut [initial_memory, zero_8, initial_bytes, false_i1], ());
+    let initial_array = builder.bitcast(initial_memory, ptr_type, llvm_str!(b"array\0"));
 
-    let cache_size = builder.uint(int32, machine.cache_size as u64);
-    let array = builder.call(malloc, &mut [cache_size], llvm_str!(b"array\0"));
-    builder.call(memset, &mut vec![array, zero_value, cache_size, one_32, false_i1], ());
+    // NOTE(jpg): array and size only change with 'grow' memory checks
+    let array_var = Var::alloc(&builder, ptr_type, initial_array, llvm_str!(b"array_var\0"));
+    let cache_size_var = Var::alloc(
+        &builder,
+        int32,
+        initial_cache_size,
+        llvm_str!(b"cache_size_var\0"),
+    );
 
     let index_var = Var::alloc(
         &builder,
@@ -48,7 +106,7 @@ pub fn compile(machine: &BfMachine, emit_main: bool) -> (Module, LLVMString) {
     let ptr_var = Var::alloc(
         &builder,
         ptr_type,
-        builder.getelementptr(array, index_var, llvm_str!(b"ptr_value\0")),
+        builder.getelementptr(array_var, index_var, llvm_str!(b"ptr_value\0")),
         llvm_str!(b"ptr_var\0"),
     );
 
@@ -60,6 +118,102 @@ pub fn compile(machine: &BfMachine, emit_main: bool) -> (Module, LLVMString) {
     // TODO(jpg): rewrite this as lambda expression if possible
     macro_rules! allow_write { () => {{ loop_abort_depth == 0 }} }
 
+    // NOTE(jpg): applies the memory check to a new index and evaluates to the checked index
+    macro_rules! check_index {
+        ($index:expr) => {{
+            let index: Value = $index;
+            match machine.memory_overflow {
+                Undefined => index,
+                Wrap => builder.urem(index, cache_size_var, llvm_str!(b"index\0")),
+                Abort => {
+                    let success_bb = function.append_basic_block(llvm_str!(b"check_success\0"));
+                    if abort_bb.is_none() {
+                        abort_bb = Some(function.append_basic_block(llvm_str!(b"check_abort\0")));
+                    }
+
+                    let cmp = builder.icmp(LLVMIntULT, index, cache_size_var, llvm_str!(b"cmp\0"));
+                    builder.cond_br(cmp, success_bb, abort_bb.unwrap());
+
+                    bb = success_bb;
+                    builder = Builder::new(&module, bb);
+                    index
+                }
+                Grow => {
+                    let success_bb = function.append_basic_block(llvm_str!(b"check_success\0"));
+                    let check_bb = function.append_basic_block(llvm_str!(b"check_grow\0"));
+                    let grow_bb = function.append_basic_block(llvm_str!(b"grow\0"));
+                    if abort_bb.is_none() {
+                        abort_bb = Some(function.append_basic_block(llvm_str!(b"check_abort\0")));
+                    }
+                    if grow_memory.is_none() {
+                        grow_memory = Some(module.add_function(
+                            llvm_str!(b"grow_memory\0"),
+                            &mut [ptr_type, int32, int32],
+                            ptr_type,
+                        ));
+                    }
+
+                    // if index < cache_size { goto success; } else { goto check; }
+                    let cmp = builder.icmp(LLVMIntULT, index, cache_size_var, llvm_str!(b"cmp\0"));
+                    builder.cond_br(cmp, success_bb, check_bb);
+
+                    // NOTE(jpg): negative indices are larger than the limit as unsigned integers
+                    // check: if index > max_grow_index { goto abort; } else { goto grow; }
+                    builder = Builder::new(&module, check_bb);
+                    let max_index = builder.uint(int32, machine.max_grow_index() as u64);
+                    let cmp = builder.icmp(LLVMIntUGT, index, max_index, llvm_str!(b"cmp\0"));
+                    builder.cond_br(cmp, abort_bb.unwrap(), grow_bb);
+
+                    // grow: new_array = grow_memory(array, cache_size, index * 2);
+                    //       if new_array == null { goto abort; } else { goto grown; }
+                    builder = Builder::new(&module, grow_bb);
+                    let two = builder.uint(int32, 2);
+                    let new_size = builder.mul(index, two, llvm_str!(b"new_size\0"));
+                    let old_size = cache_size_var.load(&builder);
+                    let new_array = builder.call(
+                        grow_memory.unwrap(),
+                        &mut [array_var.load(&builder), old_size, new_size],
+                        llvm_str!(b"new_array\0"),
+                    );
+                    let grown_bb = function.append_basic_block(llvm_str!(b"grown\0"));
+                    let null = builder.null(ptr_type);
+                    let cmp = builder.icmp(LLVMIntEQ, new_array, null, llvm_str!(b"cmp\0"));
+                    builder.cond_br(cmp, abort_bb.unwrap(), grown_bb);
+
+                    // grown: array = new_array; ptr = &array[index_var]; goto success;
+                    builder = Builder::new(&module, grown_bb);
+                    array_var.store(&builder, new_array);
+                    cache_size_var.store(&builder, new_size);
+                    // NOTE(jpg): realloc may move the memory, ptr must not point into the old one
+                    ptr_var.store(
+                        &builder,
+                        builder.getelementptr(array_var, index_var, llvm_str!(b"ptr\0")),
+                    );
+                    builder.br(success_bb);
+
+                    bb = success_bb;
+                    builder = Builder::new(&module, bb);
+                    index
+                }
+            }
+        }}
+    }
+
+    // NOTE(jpg): evaluates to a pointer to ptr[offset], the memory check only applies to offsets
+    macro_rules! cell_ptr {
+        ($offset:expr) => {{
+            let offset: i64 = $offset;
+            if offset == 0 {
+                ptr_var.load(&builder)
+            } else {
+                let offset = builder.sint(int32, offset);
+                let index = builder.add(index_var, offset, llvm_str!(b"index\0"));
+                let index = check_index!(index);
+                builder.getelementptr(array_var, index, llvm_str!(b"ptr\0"))
+            }
+        }}
+    }
+
     for (i, insn) in machine.instructions.list.iter().enumerate() {
 
         if !allow_write!() {
@@ -74,59 +228,189 @@ pub fn compile(machine: &BfMachine, emit_main: bool) -> (Module, LLVMString) {
             }
         }
 
+        if let Some(position) = machine.instructions.position(i) {
+            let location = debug_location(position);
+            module.set_debug_location(location);
+            builder.set_debug_location(location);
+        }
+
         match *insn {
 
-            BfInstruction::SetValue(value) => {
-                let value = builder.sint(value_type, value as i64);
-                builder.store(value, ptr_var);
+            BfInstruction::SetValue(offset, value) => {
+                let ptr = cell_ptr!(offset);
+                let value = builder.sint(value_type, value);
+                builder.store(value, ptr);
             }
 
-            BfInstruction::AddValue(value) => {
-                let lhs = builder.load(ptr_var, llvm_str!(b"val\0"));
-                let rhs = builder.sint(value_type, value as i64);
+            BfInstruction::AddValue(offset, value) => {
+                let ptr = cell_ptr!(offset);
+                let lhs = builder.load(ptr, llvm_str!(b"val\0"));
+                let rhs = builder.sint(value_type, value);
                 let sum = builder.add(lhs, rhs, llvm_str!(b"sum\0"));
-                builder.store(sum, ptr_var);
+                builder.store(sum, ptr);
             }
 
             BfInstruction::AddPointer(value) => {
+                let value = builder.sint(int32, value);
+                let index = builder.add(index_var, value, llvm_str!(b"index\0"));
+                let index = check_index!(index);
+                index_var.store(&builder, index);
+
+                ptr_var.store(
+                    &builder,
+                    builder.getelementptr(array_var, index_var, llvm_str!(b"ptr\0")),
+                );
+            }
 
-                let value = builder.sint(int32, value as i64);
-                builder.add(index_var, value, index_var);
+            BfInstruction::MulAdd { offset, factor } => {
+                let mul_add_bb = function.append_basic_block(llvm_str!(b"mul-add\0"));
+                let mul_add_footer_bb =
+                    function.append_basic_block(llvm_str!(b"mul-add-footer\0"));
+
+                // NOTE(jpg): the original loop does not touch ptr[offset] if *ptr == 0
+                // if *ptr == 0 { goto mul_add_footer; } else { goto mul_add; }
+                let value = builder.load(ptr_var, llvm_str!(b"val\0"));
+                let cmp = builder.icmp(LLVMIntEQ, value, zero_value, llvm_str!(b"cmp\0"));
+                builder.cond_br(cmp, mul_add_footer_bb, mul_add_bb);
+
+                // mul_add: ptr[offset] += *ptr * factor; goto mul_add_footer;
+                bb = mul_add_bb;
+                builder = Builder::new(&module, bb);
+
+                let target = cell_ptr!(offset);
+
+                let factor = builder.sint(value_type, factor);
+                let product = builder.mul(value, factor, llvm_str!(b"product\0"));
+                let lhs = builder.load(target, llvm_str!(b"val\0"));
+                let sum = builder.add(lhs, product, llvm_str!(b"sum\0"));
+                builder.store(sum, target);
+                builder.br(mul_add_footer_bb);
+
+                bb = mul_add_footer_bb;
+                builder = Builder::new(&module, bb);
+            }
 
-                match machine.memory_overflow {
-                    Undefined => {} // no op
-                    Wrap => {
-                        builder.urem(index_var, cache_size, index_var);
+            BfInstruction::ScanZero(stride)
+                if (stride == 1 || stride == -1 && has_memrchr) && machine.cell_bits == 8 &&
+                       machine.memory_overflow != Wrap => {
+
+                // NOTE(jpg): memchr/memrchr only work on bytes and cannot wrap around
+                let size_type = module.int_ptr_type();
+                let start = ptr_var.load(&builder);
+                let index = index_var.load(&builder);
+                let cache_size = cache_size_var.load(&builder);
+
+                // ptr = memchr(ptr, 0, cache_size - index); or
+                // ptr = memrchr(array, 0, index + 1);
+                let found = if stride == 1 {
+                    if memchr.is_none() {
+                        memchr = Some(module.add_function(
+                            llvm_str!(b"memchr\0"),
+                            &mut [ptr_type, int32, size_type],
+                            ptr_type,
+                        ));
                     }
-                    Abort => {
-                        let success_bb = function.append_basic_block(llvm_str!(b"check_success\0"));
-                        if abort_bb.is_none() {
-                            abort_bb =
-                                Some(function.append_basic_block(llvm_str!(b"check_abort\0")));
-                        }
-
-                        let cmp =
-                            builder.icmp(LLVMIntULT, index_var, cache_size, llvm_str!(b"cmp\0"));
-                        builder.cond_br(cmp, success_bb, abort_bb.unwrap());
-
-                        bb = success_bb;
-                        builder = Builder::new(&module, bb);
+                    let size = builder.sub(cache_size, index, llvm_str!(b"size\0"));
+                    let size = builder.int_cast(size, size_type, llvm_str!(b"size\0"));
+                    let zero = builder.uint(int32, 0);
+                    builder.call(memchr.unwrap(), &mut [start, zero, size], llvm_str!(b"found\0"))
+                } else {
+                    if memrchr.is_none() {
+                        memrchr = Some(module.add_function(
+                            llvm_str!(b"memrchr\0"),
+                            &mut [ptr_type, int32, size_type],
+                            ptr_type,
+                        ));
                     }
-                }
+                    let array = array_var.load(&builder);
+                    let size = builder.add(index, one_32, llvm_str!(b"size\0"));
+                    let size = builder.int_cast(size, size_type, llvm_str!(b"size\0"));
+                    let zero = builder.uint(int32, 0);
+                    builder.call(memrchr.unwrap(), &mut [array, zero, size], llvm_str!(b"found\0"))
+                };
+
+                // NOTE(jpg): if there is no zero cell the pointer leaves the memory just like the
+                // original loop; the memory check decides what happens next
+                let found_index = builder.ptr_diff(found, array_var, llvm_str!(b"index\0"));
+                let found_index = builder.int_cast(found_index, int32, llvm_str!(b"index\0"));
+                let not_found_index = if stride == 1 {
+                    cache_size
+                } else {
+                    builder.sint(int32, -1)
+                };
+                let null = builder.null(ptr_type);
+                let cmp = builder.icmp(LLVMIntEQ, found, null, llvm_str!(b"cmp\0"));
+                let index =
+                    builder.select(cmp, not_found_index, found_index, llvm_str!(b"index\0"));
+
+                let index = check_index!(index);
+                index_var.store(&builder, index);
+                ptr_var.store(
+                    &builder,
+                    builder.getelementptr(array_var, index_var, llvm_str!(b"ptr\0")),
+                );
+            }
+
+            BfInstruction::ScanZero(stride) => {
+                let scan_header_bb = function.append_basic_block(llvm_str!(b"scan-header\0"));
+                let scan_body_bb = function.append_basic_block(llvm_str!(b"scan-body\0"));
+                let scan_footer_bb = function.append_basic_block(llvm_str!(b"scan-footer\0"));
+
+                // goto scan_header;
+                builder.br(scan_header_bb);
 
+                // scan_header: if *ptr == 0 { goto scan_footer; } else { goto scan_body; }
+                builder = Builder::new(&module, scan_header_bb);
+                let value = builder.load(ptr_var, llvm_str!(b"val\0"));
+                let cmp = builder.icmp(LLVMIntEQ, value, zero_value, llvm_str!(b"cmp\0"));
+                builder.cond_br(cmp, scan_footer_bb, scan_body_bb);
+
+                // scan_body: ptr += stride; goto scan_header;
+                bb = scan_body_bb;
+                builder = Builder::new(&module, bb);
+
+                let stride = builder.sint(int32, stride);
+                let index = builder.add(index_var, stride, llvm_str!(b"index\0"));
+                let index = check_index!(index);
+                index_var.store(&builder, index);
                 ptr_var.store(
                     &builder,
-                    builder.getelementptr(array, index_var, llvm_str!(b"ptr\0")),
+                    builder.getelementptr(array_var, index_var, llvm_str!(b"ptr\0")),
                 );
+                builder.br(scan_header_bb);
+
+                // scan_footer: /* after scan */
+                bb = scan_footer_bb;
+                builder = Builder::new(&module, bb);
             }
 
-            BfInstruction::Input => {
-                let value = builder.call(getchar, &mut [], llvm_str!(b"chr\0"));
-                builder.store(value, ptr_var);
+            BfInstruction::Input(offset) => {
+                let ptr = cell_ptr!(offset);
+                let chr = builder.call(getchar, &mut [], llvm_str!(b"chr\0"));
+                let value = builder.int_cast(chr, value_type, llvm_str!(b"chr\0"));
+
+                // NOTE(jpg): getchar already returns -1 on EOF
+                let eof_value = match machine.eof {
+                    EofBehaviour::MinusOne => None,
+                    EofBehaviour::Zero => Some(zero_value),
+                    EofBehaviour::Unchanged => Some(builder.load(ptr, llvm_str!(b"val\0"))),
+                };
+
+                let value = match eof_value {
+                    None => value,
+                    Some(eof_value) => {
+                        let eof = builder.sint(int32, -1);
+                        let cmp = builder.icmp(LLVMIntEQ, chr, eof, llvm_str!(b"is_eof\0"));
+                        builder.select(cmp, eof_value, value, llvm_str!(b"chr\0"))
+                    }
+                };
+                builder.store(value, ptr);
             }
 
-            BfInstruction::Output => {
-                let out = builder.load(ptr_var.load(&builder), llvm_str!(b"val\0"));
+            BfInstruction::Output(offset) => {
+                let ptr = cell_ptr!(offset);
+                let out = builder.load(ptr, llvm_str!(b"val\0"));
+                let out = builder.int_cast(out, int32, llvm_str!(b"chr\0"));
                 builder.call(putchar, &mut [out], ());
             }
 
@@ -158,7 +442,7 @@ pub fn compile(machine: &BfMachine, emit_main: bool) -> (Module, LLVMString) {
 
             BfInstruction::EndLoop => {
                 let context = loop_stack.pop().expect(
-                    "Could not find machting opening 'BeginLoop' instruction",
+                    "unmatched 'EndLoop', InstructionList guarantees balanced loops",
                 );
 
                 builder.br(context.loop_header_bb);
@@ -168,94 +452,239 @@ pub fn compile(machine: &BfMachine, emit_main: bool) -> (Module, LLVMString) {
             }
 
             BfInstruction::DebugLog => {
-                if debug_log.is_none() {
-                    debug_log = Some(module.add_function(
-                        llvm_str!(b"debug_log\0"),
-                        &mut [int32, ptr_type, int32, int32],
-                        module.void_type,
-                    ))
+                if trace.is_none() {
+                    trace = Some(TraceContext {
+                        function: module.add_function(
+                            llvm_str!(b"trace\0"),
+                            &mut [int32, int32, int32, ptr_type, int32, int32],
+                            module.void_type,
+                        ),
+                        shadow_var: module.add_global(ptr_type, llvm_str!(b"trace_shadow\0")),
+                        shadow_size_var: module.add_global(
+                            int32,
+                            llvm_str!(b"trace_shadow_size\0"),
+                        ),
+                    });
                 }
 
-                let insn_index = builder.uint(int32, i as u64);
+                // NOTE(jpg): line 0 marks an unknown position
+                let position = machine.instructions.position(i).unwrap_or(SourcePosition {
+                    line: 0,
+                    column: 0,
+                });
+                let insn_index = builder.uint(int32, i as u64 + 1);
+                let line = builder.uint(int32, position.line as u64);
+                let column = builder.uint(int32, position.column as u64);
+                let array = array_var.load(&builder);
+                let cache_size = cache_size_var.load(&builder);
                 let index = index_var.load(&builder);
-                builder.call(debug_log.unwrap(), &mut [insn_index, array, cache_size, index], ());
+                builder.call(
+                    trace.as_ref().unwrap().function,
+                    &mut [insn_index, line, column, array, cache_size, index],
+                    (),
+                );
             }
         }
     }
 
+    // NOTE(jpg): frees the memory and the shadow copy of the trace
+    let free_memory = |builder: &Builder| {
+        let memory = builder.bitcast(array_var, byte_ptr_type, llvm_str!(b"memory\0"));
+        builder.call(free, &mut [memory], ());
+
+        if let Some(ref trace) = trace {
+            let shadow = builder.bitcast(trace.shadow_var, byte_ptr_type, llvm_str!(b"shadow\0"));
+            builder.call(free, &mut [shadow], ());
+            trace.shadow_var.store(builder, builder.null(ptr_type));
+            trace.shadow_size_var.store(builder, builder.uint(int32, 0));
+        }
+    };
+
     if allow_write!() {
         // NOTE(jpg): succsess: free memory and exit
         let result = builder.load(ptr_var, llvm_str!(b"val\0"));
-        let result = builder.sext_or_bitcast(result, int32, llvm_str!(b"val\0"));
-        builder.call(free, &mut [array], ());
+        let result = builder.int_cast(result, int32, llvm_str!(b"val\0"));
+        free_memory(&builder);
         builder.ret(result);
     }
 
     if let Some(bb) = abort_bb {
         // NOTE(jpg): abort
         let builder = Builder::new(&module, bb);
-        builder.call(free, &mut [array], ());
+        free_memory(&builder);
         builder.ret(builder.sint(int32, -1));
     }
 
-    if let Some(debug_log) = debug_log {
-
-        // Output layout: <instruction> <index> <memory>
-        // TODO(jpg): simplify this debug call, maybe by calling an external function
-
-        let mut bb = debug_log.append_basic_block(llvm_str!(b"entry\0"));
-        let mut builder = Builder::new(&module, bb);
+    // NOTE(jpg): the helper functions have no debug info
+    module.set_debug_location(None);
 
-        let insn_index = debug_log.get_param(0);
-        let array = debug_log.get_param(1);
-        let cache_size = debug_log.get_param(2);
-        let index = debug_log.get_param(3);
+    let realloc = if grow_memory.is_some() || trace.is_some() {
+        Some(module.add_function(
+            llvm_str!(b"realloc\0"),
+            &mut [byte_ptr_type, int32],
+            byte_ptr_type,
+        ))
+    } else {
+        None
+    };
 
-        let before_bb = bb;
-        let entry_bb = debug_log.append_basic_block(llvm_str!(b"loop-cond\0"));
-        let body_bb = debug_log.append_basic_block(llvm_str!(b"loop-body\0"));
-        let exit_bb = debug_log.append_basic_block(llvm_str!(b"loop-exit\0"));
+    if let Some(grow_memory) = grow_memory {
 
-        builder.call(putchar, &mut [builder.sint(value_type, '\n' as i64)], ());
+        // array = realloc(array, new_size); if array == null { return null; }
+        // memset(array + old_size, 0, new_size - old_size); return array;
+        let realloc = realloc.unwrap();
 
-        emit_print_char(&module, &builder, insn_index, 6, putchar, value_type);
-        builder.call(putchar, &mut [builder.sint(value_type, ' ' as i64)], ());
-        emit_print_char(&module, &builder, index, 6, putchar, value_type);
+        let bb = grow_memory.append_basic_block(llvm_str!(b"entry\0"));
+        let failed_bb = grow_memory.append_basic_block(llvm_str!(b"failed\0"));
+        let clear_bb = grow_memory.append_basic_block(llvm_str!(b"clear\0"));
+        let builder = Builder::new(&module, bb);
 
-        // int i = 0; goto entry;
-        let counter_before = builder.uint(int32, 0);
-        builder.br(entry_bb);
+        let array = grow_memory.get_param(0);
+        let old_size = grow_memory.get_param(1);
+        let new_size = grow_memory.get_param(2);
+        let cell_bytes = builder.uint(int32, cell_bytes as u64);
+
+        let memory = builder.bitcast(array, byte_ptr_type, llvm_str!(b"memory\0"));
+        let new_bytes = builder.mul(new_size, cell_bytes, llvm_str!(b"new_bytes\0"));
+        let memory = builder.call(realloc, &mut [memory, new_bytes], llvm_str!(b"memory\0"));
+        let array = builder.bitcast(memory, ptr_type, llvm_str!(b"array\0"));
+        let null = builder.null(ptr_type);
+        let cmp = builder.icmp(LLVMIntEQ, array, null, llvm_str!(b"cmp\0"));
+        builder.cond_br(cmp, failed_bb, clear_bb);
+
+        // NOTE(jpg): realloc keeps the old memory on failure, the caller frees it on abort
+        let builder = Builder::new(&module, failed_bb);
+        builder.ret(null);
+
+        let builder = Builder::new(&module, clear_bb);
+        let tail = builder.getelementptr(array, old_size, llvm_str!(b"tail\0"));
+        let tail = builder.bitcast(tail, byte_ptr_type, llvm_str!(b"tail\0"));
+        let tail_size = builder.sub(new_size, old_size, llvm_str!(b"tail_size\0"));
+        let tail_bytes = builder.mul(tail_size, cell_bytes, llvm_str!(b"tail_bytes\0"));
+        builder.call(memset, &mut [tail, zero_8, tail_bytes, false_i1], ());
+        builder.ret(array);
+    }
 
-        // entry: if i != cache_size { goto body; } else { goto exit; }
-        builder = Builder::new(&module, entry_bb);
-        let counter_entry_phi = builder.phi(int32, llvm_str!(b"i\0"));
-        let counter_entry = counter_entry_phi.value;
-        let cmp = builder.icmp(LLVMIntNE, counter_entry, cache_size, llvm_str!(b"cmp\0"));
+    if let Some(ref trace) = trace {
+
+        // Writes a `trace::TraceRecord` with the cells that differ from the shadow copy of the
+        // memory and updates the shadow copy, which grows along with the memory
+        let dprintf = module.add_variadic_function(
+            llvm_str!(b"dprintf\0"),
+            &mut [int32, byte_ptr_type],
+            int32,
+        );
+        let int64 = module.int_type(64);
+        let realloc = realloc.unwrap();
+
+        let function = trace.function;
+        let insn_index = function.get_param(0);
+        let line = function.get_param(1);
+        let column = function.get_param(2);
+        let array = function.get_param(3);
+        let cache_size = function.get_param(4);
+        let index = function.get_param(5);
+
+        let entry_bb = function.append_basic_block(llvm_str!(b"entry\0"));
+        let grow_bb = function.append_basic_block(llvm_str!(b"grow-shadow\0"));
+        let header_bb = function.append_basic_block(llvm_str!(b"header\0"));
+        let cond_bb = function.append_basic_block(llvm_str!(b"loop-cond\0"));
+        let body_bb = function.append_basic_block(llvm_str!(b"loop-body\0"));
+        let changed_bb = function.append_basic_block(llvm_str!(b"changed\0"));
+        let next_bb = function.append_basic_block(llvm_str!(b"loop-next\0"));
+        let exit_bb = function.append_basic_block(llvm_str!(b"loop-exit\0"));
+
+        // entry: if shadow_size < cache_size { goto grow; } else { goto header; }
+        let builder = Builder::new(&module, entry_bb);
+        let fd = builder.sint(int32, machine.trace_fd as i64);
+        let cell_bytes = builder.uint(int32, cell_bytes as u64);
+        let counter_var = Var::alloc(&builder, int32, builder.uint(int32, 0), llvm_str!(b"i\0"));
+        let separator_var = Var::alloc(
+            &builder,
+            byte_ptr_type,
+            builder.global_string("", llvm_str!(b"separator\0")),
+            llvm_str!(b"separator_var\0"),
+        );
+        let shadow_size = trace.shadow_size_var.load(&builder);
+        let cmp = builder.icmp(LLVMIntULT, shadow_size, cache_size, llvm_str!(b"cmp\0"));
+        builder.cond_br(cmp, grow_bb, header_bb);
+
+        // grow: shadow = realloc(shadow, cache_size);
+        //       memset(shadow + shadow_size, 0, cache_size - shadow_size); goto header;
+        let builder = Builder::new(&module, grow_bb);
+        let shadow = builder.bitcast(trace.shadow_var, byte_ptr_type, llvm_str!(b"shadow\0"));
+        let new_bytes = builder.mul(cache_size, cell_bytes, llvm_str!(b"new_bytes\0"));
+        let shadow = builder.call(realloc, &mut [shadow, new_bytes], llvm_str!(b"shadow\0"));
+        let shadow = builder.bitcast(shadow, ptr_type, llvm_str!(b"shadow\0"));
+        let tail = builder.getelementptr(shadow, shadow_size, llvm_str!(b"tail\0"));
+        let tail = builder.bitcast(tail, byte_ptr_type, llvm_str!(b"tail\0"));
+        let tail_size = builder.sub(cache_size, shadow_size, llvm_str!(b"tail_size\0"));
+        let tail_bytes = builder.mul(tail_size, cell_bytes, llvm_str!(b"tail_bytes\0"));
+        builder.call(memset, &mut [tail, zero_8, tail_bytes, false_i1], ());
+        trace.shadow_var.store(&builder, shadow);
+        trace.shadow_size_var.store(&builder, cache_size);
+        builder.br(header_bb);
+
+        // header: dprintf(fd, header, insn_index, index, line, column); goto cond;
+        // NOTE(jpg): keep in sync with `trace::TraceRecord`
+        let builder = Builder::new(&module, header_bb);
+        let header = builder.global_string(
+            "{\"insn\":%u,\"ptr\":%d,\"line\":%u,\"column\":%u,\"cells\":[",
+            llvm_str!(b"header\0"),
+        );
+        let header_without_position = builder.global_string(
+            "{\"insn\":%u,\"ptr\":%d,\"cells\":[",
+            llvm_str!(b"header\0"),
+        );
+        let zero = builder.uint(int32, 0);
+        let has_position = builder.icmp(LLVMIntNE, line, zero, llvm_str!(b"has_position\0"));
+        let header = builder.select(
+            has_position,
+            header,
+            header_without_position,
+            llvm_str!(b"header\0"),
+        );
+        builder.call(dprintf, &mut [fd, header, insn_index, index, line, column], ());
+        builder.br(cond_bb);
+
+        // cond: if i != cache_size { goto body; } else { goto exit; }
+        let builder = Builder::new(&module, cond_bb);
+        let cmp = builder.icmp(LLVMIntNE, counter_var, cache_size, llvm_str!(b"cmp\0"));
         builder.cond_br(cmp, body_bb, exit_bb);
 
-        // body: { .. } goto entry;
-        builder = Builder::new(&module, body_bb);
-
-        let ptr = builder.getelementptr(array, counter_entry, llvm_str!(b"ptr\0"));
-        let val = builder.load(ptr, llvm_str!(b"val\0"));
-
-        builder.call(putchar, &mut [val], ());
-        builder.call(putchar, &mut [builder.sint(value_type, '|' as i64)], ());
-
-        let counter_body = builder.add(counter_entry, builder.uint(int32, 1), llvm_str!(b"i\0"));
-        builder.br(entry_bb);
-
-        // exit: { ... }
-        bb = exit_bb;
-        builder = Builder::new(&module, bb);
-
-        builder.call(putchar, &mut [builder.sint(value_type, '\n' as i64)], ());
-
+        // body: if array[i] != shadow[i] { goto changed; } else { goto next; }
+        let builder = Builder::new(&module, body_bb);
+        let ptr = builder.getelementptr(array, counter_var, llvm_str!(b"ptr\0"));
+        let value = builder.load(ptr, llvm_str!(b"val\0"));
+        let shadow_ptr =
+            builder.getelementptr(trace.shadow_var, counter_var, llvm_str!(b"shadow_ptr\0"));
+        let shadow_value = builder.load(shadow_ptr, llvm_str!(b"shadow_val\0"));
+        let cmp = builder.icmp(LLVMIntNE, value, shadow_value, llvm_str!(b"cmp\0"));
+        builder.cond_br(cmp, changed_bb, next_bb);
+
+        // changed: shadow[i] = array[i]; dprintf(fd, "%s[%u,%lld]", separator, i, array[i]);
+        //          separator = ","; goto next;
+        let builder = Builder::new(&module, changed_bb);
+        builder.store(value, shadow_ptr);
+        let cell_format = builder.global_string("%s[%u,%lld]", llvm_str!(b"cell\0"));
+        let separator = separator_var.load(&builder);
+        let counter = counter_var.load(&builder);
+        let value = builder.int_cast(value, int64, llvm_str!(b"val\0"));
+        builder.call(dprintf, &mut [fd, cell_format, separator, counter, value], ());
+        separator_var.store(&builder, builder.global_string(",", llvm_str!(b"separator\0")));
+        builder.br(next_bb);
+
+        // next: i += 1; goto cond;
+        let builder = Builder::new(&module, next_bb);
+        let counter = builder.add(counter_var, one_32, llvm_str!(b"i\0"));
+        counter_var.store(&builder, counter);
+        builder.br(cond_bb);
+
+        // exit: dprintf(fd, "]}\n");
+        let builder = Builder::new(&module, exit_bb);
+        let footer = builder.global_string("]}\n", llvm_str!(b"footer\0"));
+        builder.call(dprintf, &mut [fd, footer], ());
         builder.ret_void();
-
-        //NOTE(jpg): adding phi incomming later
-        counter_entry_phi.add_incoming(counter_before, before_bb);
-        counter_entry_phi.add_incoming(counter_body, body_bb);
     }
 
     if emit_main {
@@ -266,33 +695,32 @@ pub fn compile(machine: &BfMachine, emit_main: bool) -> (Module, LLVMString) {
         builder.ret(result);
     }
 
+    if let Some(ref debug_info) = debug_info {
+        debug_info.finalize();
+    }
+
     (module, function_name)
 }
 
-fn emit_print_char(
-    module: &Module,
-    builder: &Builder,
-    value: Value,
-    decimal_places: u32,
-    putchar: Function,
-    putchar_type: Type,
-) {
-    for decimal_place in (0..decimal_places - 1).rev() {
-        let div_value = u64::pow(10, decimal_place);
-        let mod_value = 10;
-        let zero_value = '0' as u64;
-
-        let div_value = builder.uint(module.i32_type, div_value);
-        let mod_value = builder.uint(module.i32_type, mod_value);
-        let zero_value = builder.uint(module.i32_type, zero_value);
-
-        let name = llvm_str!(b"char\0");
-        let chr = value;
-        let chr = builder.udiv(chr, div_value, name);
-        let chr = builder.urem(chr, mod_value, name);
-        let chr = builder.add(chr, zero_value, name);
-        let chr = builder.trunc(chr, putchar_type, name);
-        builder.call(putchar, &mut [chr], ());
+/// Whether the C library of `triple` has the GNU extension `memrchr`.
+fn has_memrchr(triple: &str) -> bool {
+    // NOTE(jpg): glibc, musl and bionic all provide it, macOS and Windows do not
+    triple.contains("-linux")
+}
+
+/// Splits `source_path` into the file name and compilation directory of the debug info.
+fn split_source_path(source_path: &str) -> (String, String) {
+    let path = Path::new(source_path);
+    match (path.parent(), path.file_name()) {
+        (Some(directory), Some(file_name)) if path.is_absolute() => {
+            (file_name.to_string_lossy().into_owned(), directory.to_string_lossy().into_owned())
+        }
+        _ => {
+            let directory = env::current_dir()
+                .map(|directory| directory.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            (source_path.to_owned(), directory)
+        }
     }
 }
 
@@ -300,3 +728,10 @@ struct LoopContext {
     loop_header_bb: BasicBlock,
     loop_footer_bb: BasicBlock,
 }
+
+struct TraceContext {
+    function: Function,
+    /// Copy of the memory at the last trace record
+    shadow_var: Var,
+    shadow_size_var: Var,
+}
@@ -0,0 +1,444 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+
+use bf::*;
+use interpreter::{Interpreter, Step};
+use trace::FdWriter;
+
+const HELP: &str = "\
+step [N]        s  execute the next N instructions (default 1)
+next            n  like step, but runs a loop starting at the next instruction to its end
+continue        c  run until a breakpoint, a watched cell changes or the program terminates
+until LOCATION  u  run to LOCATION
+break LOCATION  b  stop before the instruction at LOCATION
+delete [ID]     d  remove breakpoint ID or all breakpoints
+watch CELL      w  stop when the value of CELL changes
+unwatch [CELL]     remove the watch on CELL or all watches
+tape [RADIUS]   t  print the cells up to RADIUS (default 8) away from the pointer
+where           l  print the next instruction and its source line
+info            i  list breakpoints and watches
+restart         r  restart the program
+quit            q  exit the debugger
+help            h  print this help
+LOCATION is LINE, LINE:COLUMN or @INDEX, the instruction index printed by 'where'.
+An empty line repeats the previous command.
+";
+
+const DEFAULT_TAPE_RADIUS: i64 = 8;
+
+struct Breakpoint {
+    id: usize,
+    pc: usize,
+}
+
+struct Watch {
+    cell: u32,
+    value: i64,
+}
+
+/// Output of the program; remembers whether the last line has been terminated
+struct ProgramOutput<W: Write> {
+    inner: W,
+    line_open: bool,
+}
+
+impl<W: Write> Write for ProgramOutput<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if written > 0 {
+            self.line_open = buf[written - 1] != b'\n';
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Interactive step debugger on top of `interpreter::Interpreter`.
+///
+/// Commands are read line by line from `commands`, see `HELP`; the debugger's messages are written
+/// to `console`, the program's output to `output`. When the program reads input and no input is
+/// left, the debugger asks for another line on `console` and reads it from `commands`.
+pub struct Debugger<'a, C: BufRead, M: Write, W: Write> {
+    interpreter: Interpreter<'a>,
+    source: &'a str,
+    commands: C,
+    console: M,
+    output: ProgramOutput<W>,
+    trace: FdWriter,
+    input: VecDeque<u8>,
+    input_eof: bool,
+    breakpoints: Vec<Breakpoint>,
+    next_breakpoint_id: usize,
+    watches: Vec<Watch>,
+    last_command: String,
+}
+
+impl<'a, C: BufRead, M: Write, W: Write> Debugger<'a, C, M, W> {
+    /// `source` is the text `machine` was parsed from; used to print source lines.
+    pub fn new(
+        machine: &'a BfMachine,
+        source: &'a str,
+        commands: C,
+        console: M,
+        output: W,
+    ) -> Self {
+        Debugger {
+            interpreter: Interpreter::new(machine),
+            source,
+            commands,
+            console,
+            output: ProgramOutput {
+                inner: output,
+                line_open: false,
+            },
+            trace: FdWriter::new(machine.trace_fd),
+            input: VecDeque::new(),
+            input_eof: false,
+            breakpoints: Vec::new(),
+            next_breakpoint_id: 1,
+            watches: Vec::new(),
+            last_command: String::new(),
+        }
+    }
+
+    pub fn interpreter(&self) -> &Interpreter<'a> {
+        &self.interpreter
+    }
+
+    /// Prompts for commands until `quit` or the end of `commands`.
+    ///
+    /// Returns the result of the program if it has terminated.
+    pub fn run(&mut self) -> io::Result<Option<i32>> {
+        self.print_location()?;
+
+        loop {
+            write!(self.console, "(bf) ")?;
+            self.console.flush()?;
+
+            let mut line = String::new();
+            if self.commands.read_line(&mut line)? == 0 {
+                writeln!(self.console)?;
+                break;
+            }
+            if !self.execute(line.trim())? {
+                break;
+            }
+        }
+
+        Ok(self.interpreter.result())
+    }
+
+    /// Executes a single command; returns `false` on `quit`.
+    pub fn execute(&mut self, line: &str) -> io::Result<bool> {
+        let line = if line.is_empty() {
+            self.last_command.clone()
+        } else {
+            self.last_command = line.to_owned();
+            line.to_owned()
+        };
+
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Ok(true),
+        };
+        let argument = words.next();
+
+        match (command, argument) {
+            ("s", _) | ("step", _) => match parse_number(argument, 1) {
+                Ok(count) => {
+                    let mut remaining = count;
+                    self.resume(|_| {
+                        remaining -= 1;
+                        remaining == 0
+                    })?
+                }
+                Err(message) => writeln!(self.console, "{}", message)?,
+            },
+            ("n", None) | ("next", None) => {
+                let pc = self.interpreter.pc();
+                match self.interpreter.loop_end(pc) {
+                    Some(end) => self.resume(|interpreter| interpreter.pc() == end + 1)?,
+                    None => self.resume(|_| true)?,
+                }
+            }
+            ("c", None) | ("continue", None) => self.resume(|_| false)?,
+            ("u", Some(location)) | ("until", Some(location)) => {
+                match self.resolve_location(location) {
+                    Ok(target) => self.resume(|interpreter| interpreter.pc() == target)?,
+                    Err(message) => writeln!(self.console, "{}", message)?,
+                }
+            }
+            ("b", Some(location)) | ("break", Some(location)) => {
+                match self.resolve_location(location) {
+                    Ok(pc) => {
+                        let id = self.next_breakpoint_id;
+                        self.next_breakpoint_id += 1;
+                        writeln!(self.console, "breakpoint {} at {}", id, self.describe(pc))?;
+                        self.breakpoints.push(Breakpoint { id, pc });
+                    }
+                    Err(message) => writeln!(self.console, "{}", message)?,
+                }
+            }
+            ("d", id) | ("delete", id) => match id {
+                None => {
+                    self.breakpoints.clear();
+                    writeln!(self.console, "deleted all breakpoints")?;
+                }
+                Some(id) => {
+                    let count = self.breakpoints.len();
+                    self.breakpoints.retain(|breakpoint| breakpoint.id.to_string() != id);
+                    if self.breakpoints.len() == count {
+                        writeln!(self.console, "no breakpoint {}", id)?;
+                    }
+                }
+            },
+            ("w", Some(cell)) | ("watch", Some(cell)) => match cell.parse::<u32>() {
+                Ok(cell) => {
+                    let value = self.cell_value(cell);
+                    writeln!(self.console, "watching cell {} = {}", cell, value)?;
+                    self.watches.retain(|watch| watch.cell != cell);
+                    self.watches.push(Watch { cell, value });
+                }
+                Err(_) => writeln!(self.console, "invalid cell index '{}'", cell)?,
+            },
+            ("unwatch", cell) => match cell {
+                None => {
+                    self.watches.clear();
+                    writeln!(self.console, "deleted all watches")?;
+                }
+                Some(cell) => {
+                    let count = self.watches.len();
+                    self.watches.retain(|watch| watch.cell.to_string() != cell);
+                    if self.watches.len() == count {
+                        writeln!(self.console, "cell {} is not watched", cell)?;
+                    }
+                }
+            },
+            ("t", _) | ("tape", _) => match parse_number(argument, DEFAULT_TAPE_RADIUS as usize) {
+                Ok(radius) => self.print_tape(radius as i64)?,
+                Err(message) => writeln!(self.console, "{}", message)?,
+            },
+            ("l", None) | ("where", None) => self.print_location()?,
+            ("i", None) | ("info", None) => self.print_info()?,
+            ("r", None) | ("restart", None) => {
+                self.interpreter = Interpreter::new(self.interpreter.machine());
+                self.input.clear();
+                self.input_eof = false;
+                for watch in &mut self.watches {
+                    watch.value = 0;
+                }
+                self.print_location()?;
+            }
+            ("q", None) | ("quit", None) => return Ok(false),
+            ("h", None) | ("help", None) => write!(self.console, "{}", HELP)?,
+            _ => writeln!(self.console, "invalid command '{}'; try 'help'", line)?,
+        }
+
+        Ok(true)
+    }
+
+    /// Executes instructions until `stop` returns true after an instruction, a breakpoint is hit,
+    /// a watched cell changes or the program terminates.
+    fn resume<F: FnMut(&Interpreter) -> bool>(&mut self, mut stop: F) -> io::Result<()> {
+        if self.interpreter.result().is_some() {
+            return writeln!(self.console, "the program is not running; use 'restart'");
+        }
+
+        loop {
+            self.read_input_if_needed()?;
+            let step = self.interpreter.step(&mut self.input, &mut self.output, &mut self.trace)?;
+            if let Step::Finished(_) = step {
+                break;
+            }
+
+            if self.report_watches()? || stop(&self.interpreter) {
+                break;
+            }
+
+            let pc = self.interpreter.pc();
+            let breakpoint = self.breakpoints.iter().find(|breakpoint| breakpoint.pc == pc);
+            if let Some(breakpoint) = breakpoint {
+                writeln!(self.console, "breakpoint {}", breakpoint.id)?;
+                break;
+            }
+        }
+
+        self.finish_output()?;
+        self.print_location()
+    }
+
+    /// Flushes the program's output and terminates its last line on the console.
+    fn finish_output(&mut self) -> io::Result<()> {
+        self.output.flush()?;
+        if self.output.line_open {
+            self.output.line_open = false;
+            writeln!(self.console)?;
+        }
+        Ok(())
+    }
+
+    /// Asks for a line of input if the next instruction reads from the exhausted input.
+    fn read_input_if_needed(&mut self) -> io::Result<()> {
+        let insns = &self.interpreter.machine().instructions.list;
+        let next_insn = insns.get(self.interpreter.pc());
+        let reads_input = matches!(next_insn, Some(&BfInstruction::Input(_)));
+        if !reads_input || !self.input.is_empty() || self.input_eof {
+            return Ok(());
+        }
+
+        self.finish_output()?;
+        write!(self.console, "input> ")?;
+        self.console.flush()?;
+
+        let mut line = String::new();
+        if self.commands.read_line(&mut line)? == 0 {
+            writeln!(self.console)?;
+            self.input_eof = true;
+        }
+        self.input.extend(line.bytes());
+        Ok(())
+    }
+
+    /// Prints and updates the watches whose cell changed; returns whether there were any.
+    fn report_watches(&mut self) -> io::Result<bool> {
+        let mut changed = false;
+        for i in 0..self.watches.len() {
+            let value = self.cell_value(self.watches[i].cell);
+            let watch = &mut self.watches[i];
+            if value != watch.value {
+                writeln!(self.console, "cell {}: {} -> {}", watch.cell, watch.value, value)?;
+                watch.value = value;
+                changed = true;
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Resolves `LINE`, `LINE:COLUMN` or `@INDEX` to the index of an instruction.
+    ///
+    /// A source position refers to the first instruction at or after it in the same line.
+    fn resolve_location(&self, location: &str) -> Result<usize, String> {
+        let insns = &self.interpreter.machine().instructions;
+        let invalid = || {
+            format!("invalid location '{}'; expected LINE, LINE:COLUMN or @INDEX", location)
+        };
+
+        if let Some(index) = location.strip_prefix('@') {
+            return match index.parse::<usize>() {
+                Ok(index) if index >= 1 && index <= insns.list.len() => Ok(index - 1),
+                Ok(_) => Err(format!("no instruction {}", location)),
+                Err(_) => Err(invalid()),
+            };
+        }
+
+        let mut parts = location.splitn(2, ':');
+        let line = parts.next().and_then(|line| line.parse::<usize>().ok());
+        let column = match parts.next() {
+            Some(column) => column.parse::<usize>().ok(),
+            None => Some(1),
+        };
+        let (line, column) = match (line, column) {
+            (Some(line), Some(column)) => (line, column),
+            _ => return Err(invalid()),
+        };
+
+        if !insns.list.is_empty() && insns.position(0).is_none() {
+            // NOTE(jpg): e.g. instructions created by `InstructionList::from_vec`
+            return Err("source positions are unknown; use an instruction index".to_owned());
+        }
+
+        (0..insns.list.len())
+            .find(|&i| {
+                let position = insns.position(i).unwrap();
+                position.line == line && position.column >= column
+            })
+            .ok_or_else(|| format!("no instruction at {}", location))
+    }
+
+    /// Instruction index, source position and instruction of the instruction at `pc`
+    fn describe(&self, pc: usize) -> String {
+        let insns = &self.interpreter.machine().instructions;
+        let insn = match insns.list.get(pc) {
+            Some(insn) => insn,
+            // NOTE(jpg): only reachable before the first step of a program without instructions
+            None => return format!("@{}: end of program, no instructions left", pc + 1),
+        };
+        match insns.position(pc) {
+            Some(position) => {
+                format!("@{} at {}:{}: {}", pc + 1, position.line, position.column, insn)
+            }
+            None => format!("@{}: {}", pc + 1, insn),
+        }
+    }
+
+    fn print_location(&mut self) -> io::Result<()> {
+        if let Some(result) = self.interpreter.result() {
+            return if result == -1 {
+                writeln!(self.console, "program aborted by memory check")
+            } else {
+                writeln!(self.console, "program terminated with result {}", result)
+            };
+        }
+
+        let pc = self.interpreter.pc();
+        let pointer = self.interpreter.pointer();
+        writeln!(
+            self.console,
+            "{}    [{}] = {}",
+            self.describe(pc),
+            pointer,
+            self.cell_value(pointer)
+        )?;
+
+        let position = self.interpreter.machine().instructions.position(pc);
+        if let Some(snippet) = position.and_then(|position| source_snippet(self.source, position)) {
+            writeln!(self.console, "{}", snippet)?;
+        }
+        Ok(())
+    }
+
+    fn print_tape(&mut self, radius: i64) -> io::Result<()> {
+        let pointer = self.interpreter.pointer() as i64;
+        let cells: Vec<_> = (pointer - radius..pointer + radius + 1)
+            .filter(|&index| index >= 0 && (index as usize) < self.interpreter.memory().len())
+            .map(|index| {
+                let cell = format!("{}:{}", index, self.interpreter.memory()[index as usize]);
+                if index == pointer { format!("[{}]", cell) } else { cell }
+            })
+            .collect();
+        writeln!(self.console, "{}", cells.join(" "))
+    }
+
+    fn print_info(&mut self) -> io::Result<()> {
+        if self.breakpoints.is_empty() && self.watches.is_empty() {
+            return writeln!(self.console, "no breakpoints or watches");
+        }
+        for breakpoint in &self.breakpoints {
+            let insn = self.describe(breakpoint.pc);
+            writeln!(self.console, "breakpoint {} at {}", breakpoint.id, insn)?;
+        }
+        for watch in &self.watches {
+            writeln!(self.console, "watch cell {} = {}", watch.cell, watch.value)?;
+        }
+        Ok(())
+    }
+
+    /// Value of a cell; cells outside of the memory read as zero
+    fn cell_value(&self, cell: u32) -> i64 {
+        self.interpreter.memory().get(cell as usize).cloned().unwrap_or(0)
+    }
+}
+
+fn parse_number(argument: Option<&str>, default: usize) -> Result<usize, String> {
+    match argument {
+        None => Ok(default),
+        Some(argument) => match argument.parse() {
+            Ok(number) if number > 0 => Ok(number),
+            _ => Err(format!("expected a positive number instead of '{}'", argument)),
+        },
+    }
+}
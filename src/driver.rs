@@ -0,0 +1,458 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Command;
+
+use tempfile::NamedTempFile;
+
+use bf::*;
+use compiler::compile;
+use error::CompileError;
+use jit::{self, JitOutput};
+use llvm::{LLVMString, Module, OptLevel, Target};
+
+/// Generated LLVM module together with the name of its brainfuck function
+pub struct Program {
+    pub(crate) module: Module,
+    pub(crate) function_name: LLVMString,
+}
+
+impl Program {
+    /// Returns the textual LLVM IR of the module.
+    pub fn llvm_ir(&self) -> String {
+        self.module.print_to_string()
+    }
+}
+
+/// File types written by `Compiler::emit`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FileType {
+    Bitcode,
+    Assembly,
+    Object,
+}
+
+/// How `Compiler::write_executable` links the object file
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LinkOptions {
+    /// Linker executable; falls back to the `BF_LINKER` environment variable and then `clang`
+    pub linker: Option<String>,
+    /// Additional arguments passed to the linker after the default ones
+    pub args: Vec<String>,
+    /// Link a static executable
+    pub static_link: bool,
+}
+
+/// Command line conventions of the supported linkers, derived from the executable name
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LinkerFlavor {
+    /// `clang`; cross links with `-target`
+    Clang,
+    /// `cc`, `gcc` or a cross `<triple>-gcc`
+    Cc,
+    /// `ld`, `ld.lld`, `ld.gold` or `<triple>-ld`; start files and libc have to be passed with
+    /// `LinkOptions::args`
+    Ld,
+}
+
+impl LinkerFlavor {
+    pub fn from_linker(linker: &str) -> Self {
+        let name = Path::new(linker)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(linker);
+
+        if name.contains("clang") {
+            LinkerFlavor::Clang
+        } else if name == "ld" || name.starts_with("ld.") || name.ends_with("-ld") {
+            LinkerFlavor::Ld
+        } else {
+            LinkerFlavor::Cc
+        }
+    }
+}
+
+/// Compiles brainfuck source code: source → brainfuck IR → LLVM module → object file,
+/// executable or JIT execution.
+///
+/// ```ignore
+/// let compiler = Compiler::new().cell_bits(16).memory_overflow(MemoryOverflowBehaviour::Abort);
+/// let program = compiler.compile(&compiler.parse("++[->+<]>.")?)?;
+/// let result = compiler.run(&program, b"")?;
+/// ```
+pub struct Compiler {
+    cache_size: i64,
+    cell_bits: u32,
+    memory_overflow: MemoryOverflowBehaviour,
+    eof: EofBehaviour,
+    pass_manager: PassManager,
+    opt_level: OptLevel,
+    emit_debug: bool,
+    debug_at: DebugPlacement,
+    trace_fd: i32,
+    debug_source: Option<String>,
+    target: Target,
+    link_options: LinkOptions,
+    verbose: bool,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Compiler {
+            cache_size: 4096,
+            cell_bits: 8,
+            memory_overflow: MemoryOverflowBehaviour::Undefined,
+            eof: EofBehaviour::MinusOne,
+            pass_manager: PassManager::with_opt_level(3),
+            opt_level: OptLevel::O3,
+            emit_debug: false,
+            debug_at: DebugPlacement::All,
+            trace_fd: 2,
+            debug_source: None,
+            target: Target::default(),
+            link_options: LinkOptions::default(),
+            verbose: false,
+        }
+    }
+
+    /// Initial number of memory cells
+    pub fn memory_size(mut self, cache_size: i64) -> Self {
+        self.cache_size = cache_size;
+        self
+    }
+
+    /// Width of a memory cell in bits; one of 8, 16, 32 or 64
+    pub fn cell_bits(mut self, cell_bits: u32) -> Self {
+        self.cell_bits = cell_bits;
+        self
+    }
+
+    pub fn memory_overflow(mut self, memory_overflow: MemoryOverflowBehaviour) -> Self {
+        self.memory_overflow = memory_overflow;
+        self
+    }
+
+    pub fn eof(mut self, eof: EofBehaviour) -> Self {
+        self.eof = eof;
+        self
+    }
+
+    /// Sets the LLVM optimization and code generator level and selects the brainfuck IR passes of
+    /// the same level, see `PassManager::with_opt_level`; `Os` and `Oz` run all passes.
+    pub fn opt_level(mut self, opt_level: OptLevel) -> Self {
+        self.pass_manager = PassManager::with_opt_level(match opt_level {
+            OptLevel::Os | OptLevel::Oz => 3,
+            _ => opt_level.speed_level(),
+        });
+        self.opt_level = opt_level;
+        self
+    }
+
+    /// Replaces the brainfuck IR passes selected by `opt_level`
+    pub fn pass_manager(mut self, pass_manager: PassManager) -> Self {
+        self.pass_manager = pass_manager;
+        self
+    }
+
+    /// Writes trace records at the places selected by `debug_at` and at `#` breakpoints in the
+    /// source code, see `trace::TraceRecord`
+    pub fn emit_debug(mut self, emit_debug: bool) -> Self {
+        self.emit_debug = emit_debug;
+        self
+    }
+
+    pub fn debug_at(mut self, debug_at: DebugPlacement) -> Self {
+        self.debug_at = debug_at;
+        self
+    }
+
+    /// File descriptor the trace records of `emit_debug` are written to; stderr by default
+    pub fn trace_fd(mut self, trace_fd: i32) -> Self {
+        self.trace_fd = trace_fd;
+        self
+    }
+
+    /// Emits DWARF debug info with line information referring to `source_path`.
+    ///
+    /// Instructions folded by the brainfuck IR passes refer to the first character folded into
+    /// them; combine with `OptLevel::O0` to step through every instruction of the source code.
+    pub fn debug_info<S: Into<String>>(mut self, source_path: S) -> Self {
+        self.debug_source = Some(source_path.into());
+        self
+    }
+
+    pub fn target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
+    }
+
+    pub fn link_options(mut self, link_options: LinkOptions) -> Self {
+        self.link_options = link_options;
+        self
+    }
+
+    /// Echoes external commands like the linker invocation to stderr
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Names of the brainfuck IR passes run by `parse` and `parse_ir`
+    pub fn pass_names(&self) -> Vec<&'static str> {
+        self.pass_manager.pass_names()
+    }
+
+    /// Parses and optimizes brainfuck source code.
+    pub fn parse(&self, source: &str) -> Result<BfMachine, CompileError> {
+        let insns = if self.emit_debug {
+            InstructionList::from_chars_with_breakpoints(source.chars())
+        } else {
+            InstructionList::from_chars(source.chars())
+        };
+        let insns = insns.map_err(|err| CompileError::Parse(format_parse_error(source, &err)))?;
+        Ok(self.create_machine(insns))
+    }
+
+    /// Parses and optimizes textual brainfuck IR, see `InstructionList::from_ir`.
+    pub fn parse_ir(&self, ir: &str) -> Result<BfMachine, CompileError> {
+        let insns = InstructionList::from_ir(ir).map_err(|err| {
+            CompileError::Parse(format_ir_parse_error(&err))
+        })?;
+        Ok(self.create_machine(insns))
+    }
+
+    fn create_machine(&self, mut insns: InstructionList) -> BfMachine {
+        // NOTE(jpg): the passes keep `DebugLog`s in place, so the records show the pointer and the
+        // loops of the source code
+        if self.emit_debug {
+            insns.insert_debug_logs(self.debug_at);
+        }
+        self.pass_manager.run(&mut insns);
+
+        BfMachine {
+            cache_size: self.cache_size,
+            cell_bits: self.cell_bits,
+            instructions: insns,
+            memory_overflow: self.memory_overflow,
+            eof: self.eof,
+            trace_fd: self.trace_fd,
+        }
+    }
+
+    /// Generates the unoptimized LLVM module, including a `main` function.
+    pub fn codegen(&self, machine: &BfMachine) -> Program {
+        let (module, function_name) =
+            compile(machine, true, &self.target, self.debug_source.as_deref());
+        Program {
+            module,
+            function_name,
+        }
+    }
+
+    /// Verifies and optimizes the LLVM module.
+    pub fn optimize(&self, program: &Program) -> Result<(), CompileError> {
+        program.module.verify().map_err(CompileError::Verify)?;
+        program.module.optimize(self.opt_level);
+        Ok(())
+    }
+
+    /// Generates and optimizes the LLVM module.
+    pub fn compile(&self, machine: &BfMachine) -> Result<Program, CompileError> {
+        let program = self.codegen(machine);
+        self.optimize(&program)?;
+        Ok(program)
+    }
+
+    /// Writes the compiled program in the given file format to `output`.
+    pub fn emit<W: Write>(
+        &self,
+        program: &Program,
+        file_type: FileType,
+        output: &mut W,
+    ) -> Result<(), CompileError> {
+        let file = create_temp_file()?;
+        let path = temp_file_path(&file)?;
+
+        match file_type {
+            FileType::Bitcode => program.module.write_bitcode_file(path),
+            FileType::Assembly => {
+                program.module.write_assembly_file(path, &self.target, self.opt_level)
+            }
+            FileType::Object => {
+                program.module.write_object_file(path, &self.target, self.opt_level)
+            }
+        }.map_err(CompileError::Codegen)?;
+
+        let mut input = fs::File::open(path).map_err(|err| {
+            CompileError::io("failed to open temporary file", err)
+        })?;
+        io::copy(&mut input, output)
+            .and_then(|_| output.flush())
+            .map_err(|err| CompileError::io("failed to write output", err))
+    }
+
+    /// Writes an object file and links it to an executable at `path`, see `LinkOptions`.
+    pub fn write_executable(&self, program: &Program, path: &str) -> Result<(), CompileError> {
+        let obj_file = create_temp_file()?;
+        let obj_path = temp_file_path(&obj_file)?;
+        program.module.write_object_file(obj_path, &self.target, self.opt_level).map_err(
+            CompileError::Codegen,
+        )?;
+
+        let target_triple = program.module.get_target().ok_or_else(|| {
+            CompileError::Link("failed determine target triple".to_owned())
+        })?;
+
+        self.link_object_file(obj_path, path, target_triple.as_str())
+    }
+
+    fn link_object_file(
+        &self,
+        obj_path: &str,
+        exec_path: &str,
+        target_triple: &str,
+    ) -> Result<(), CompileError> {
+
+        let linker = match self.link_options.linker {
+            Some(ref linker) => linker.clone(),
+            None => env::var("BF_LINKER").unwrap_or_else(|_| "clang".to_owned()),
+        };
+        let flavor = LinkerFlavor::from_linker(&linker);
+        let arguments =
+            link_arguments(flavor, obj_path, exec_path, target_triple, &self.link_options);
+
+        if self.verbose {
+            eprintln!("{} args: {:?}", linker, arguments);
+        }
+
+        let status = Command::new(&linker).args(&arguments).status().map_err(|err| {
+            CompileError::Link(format!("failed to execute {}: {}", linker, err))
+        })?;
+
+        if status.success() {
+            Ok(())
+        } else {
+            match status.code() {
+                Some(code) => Err(CompileError::Link(format!(
+                    "{} terminated unsuccessfully with code {}.",
+                    linker,
+                    code
+                ))),
+                None => Err(CompileError::Link(format!("{} terminated unsuccessfully", linker))),
+            }
+        }
+    }
+
+    /// Executes the compiled program with `input`, capturing its output.
+    pub fn run(&self, program: &Program, input: &[u8]) -> Result<JitOutput, CompileError> {
+        self.check_jit_target()?;
+        jit::run(&program.module, program.function_name, input).map_err(CompileError::Runtime)
+    }
+
+    /// Executes the compiled program, see `jit::run_with_callbacks`.
+    pub fn run_with_callbacks<G, P>(
+        &self,
+        program: &Program,
+        getchar: G,
+        putchar: P,
+    ) -> Result<i32, CompileError>
+    where
+        G: FnMut() -> i32,
+        P: FnMut(u8),
+    {
+        self.check_jit_target()?;
+        jit::run_with_callbacks(&program.module, program.function_name, getchar, putchar)
+            .map_err(CompileError::Runtime)
+    }
+
+    fn check_jit_target(&self) -> Result<(), CompileError> {
+        match self.target.triple {
+            Some(_) => Err(CompileError::InvalidArgument(
+                "cannot execute code compiled for a custom target".to_owned(),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn format_parse_error(source: &str, error: &ParseError) -> String {
+
+    // Output layout:
+    // error: unmatched '[' at line 2, column 3
+    //  2 | ++[>+
+    //    |   ^
+    let mut result = String::new();
+
+    for unmatched in &error.unmatched_brackets {
+        let position = unmatched.position;
+        result.push_str(&format!(
+            "error: unmatched '{}' at line {}, column {}\n",
+            unmatched.bracket,
+            position.line,
+            position.column
+        ));
+        if let Some(snippet) = source_snippet(source, position) {
+            result.push_str(&snippet);
+            result.push('\n');
+        }
+    }
+
+    let count = error.unmatched_brackets.len();
+    result.push_str(&format!(
+        "aborting due to {} unmatched bracket{}",
+        count,
+        if count == 1 { "" } else { "s" }
+    ));
+    result
+}
+
+pub fn format_ir_parse_error(error: &IrParseError) -> String {
+    format!(
+        "error: {} at line {}, column {}",
+        error.message,
+        error.position.line,
+        error.position.column
+    )
+}
+
+fn create_temp_file() -> Result<NamedTempFile, CompileError> {
+    NamedTempFile::new().map_err(|err| CompileError::io("failed to create temporary file", err))
+}
+
+fn temp_file_path(file: &NamedTempFile) -> Result<&str, CompileError> {
+    file.path().to_str().ok_or_else(|| {
+        let error = io::Error::new(io::ErrorKind::InvalidData, "file name is not valid utf8");
+        CompileError::io("invalid temporary file", error)
+    })
+}
+
+/// Arguments for linking the object file at `obj_path` to an executable at `exec_path`.
+pub fn link_arguments(
+    flavor: LinkerFlavor,
+    obj_path: &str,
+    exec_path: &str,
+    target_triple: &str,
+    options: &LinkOptions,
+) -> Vec<String> {
+
+    let mut arguments = vec![obj_path.to_owned(), "-o".to_owned(), exec_path.to_owned()];
+
+    // NOTE(jpg): gcc and ld are built for a single target, cross linking needs a cross linker
+    if flavor == LinkerFlavor::Clang {
+        arguments.push("-target".to_owned());
+        arguments.push(target_triple.to_owned());
+    }
+    if options.static_link {
+        arguments.push("-static".to_owned());
+    }
+
+    arguments.extend(options.args.iter().cloned());
+    arguments
+}
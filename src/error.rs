@@ -0,0 +1,76 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Error of any stage of `driver::Compiler` and the command line interface
+#[derive(Debug)]
+pub enum CompileError {
+    /// Invalid command line arguments or compiler configuration
+    InvalidArgument(String),
+    /// Reading the input or writing the output failed
+    Io { context: String, error: io::Error },
+    /// Invalid brainfuck source code or brainfuck IR; contains the formatted diagnostics
+    Parse(String),
+    /// The generated LLVM module is invalid; contains the verifier message
+    Verify(String),
+    /// LLVM failed to emit bitcode, assembly or an object file
+    Codegen(String),
+    /// The object file could not be linked to an executable
+    Link(String),
+    /// Executing the program failed or the program was aborted by a memory check
+    Runtime(String),
+}
+
+impl CompileError {
+    pub fn io<S: Into<String>>(context: S, error: io::Error) -> Self {
+        CompileError::Io {
+            context: context.into(),
+            error,
+        }
+    }
+
+    /// Process exit code of the command line interface for this kind of error.
+    ///
+    /// Successful runs exit with the value of the current cell, so these codes can only tell
+    /// errors apart from each other, not from successful runs.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            CompileError::InvalidArgument(_) => 1,
+            CompileError::Io { .. } => 3,
+            CompileError::Parse(_) => 4,
+            CompileError::Verify(_) => 5,
+            CompileError::Codegen(_) => 6,
+            CompileError::Link(_) => 7,
+            CompileError::Runtime(_) => 8,
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CompileError::InvalidArgument(ref message) => write!(f, "{}", message),
+            CompileError::Io { ref context, ref error } => write!(f, "{}: {}", context, error),
+            CompileError::Parse(ref message) => write!(f, "{}", message),
+            CompileError::Verify(ref message) => write!(
+                f,
+                "internal compiler error: generated LLVM module is invalid\n{}",
+                message.trim_end()
+            ),
+            CompileError::Codegen(ref message) => write!(f, "code generation failed: {}", message),
+            CompileError::Link(ref message) => write!(f, "linking failed: {}", message),
+            CompileError::Runtime(ref message) => {
+                write!(f, "Error encountered during execution: {}", message)
+            }
+        }
+    }
+}
+
+impl Error for CompileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            CompileError::Io { ref error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use std::io::{self, Read, Write};
+
+use bf::*;
+use bf::MemoryOverflowBehaviour::*;
+use trace::{FdWriter, TraceRecord, TraceState};
+
+/// Executes the instructions of `machine` directly, without going through LLVM.
+///
+/// The result mirrors the function emitted by `compiler::compile`: the value of the current cell
+/// on success, or `-1` if the program was aborted by a failed memory check. Trace records are
+/// written to `machine.trace_fd`.
+pub fn interpret<R: Read, W: Write>(
+    machine: &BfMachine,
+    input: &mut R,
+    output: &mut W,
+) -> io::Result<i32> {
+    interpret_with_trace(machine, input, output, &mut FdWriter::new(machine.trace_fd))
+}
+
+/// Like `interpret`, but writes the trace records to `trace`.
+pub fn interpret_with_trace<R: Read, W: Write, T: Write>(
+    machine: &BfMachine,
+    input: &mut R,
+    output: &mut W,
+    trace: &mut T,
+) -> io::Result<i32> {
+
+    let mut interpreter = Interpreter::new(machine);
+    loop {
+        if let Step::Finished(result) = interpreter.step(input, output, trace)? {
+            return Ok(result);
+        }
+    }
+}
+
+/// Result of `Interpreter::step`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// The program continues with the instruction at `Interpreter::pc`
+    Running,
+    /// The program terminated with the result described at `interpret`
+    Finished(i32),
+}
+
+/// Executes the instructions of a machine one at a time, see `interpret`.
+pub struct Interpreter<'a> {
+    machine: &'a BfMachine,
+    jump_table: Vec<usize>,
+    memory: Vec<i64>,
+    index: u32,
+    pc: usize,
+    result: Option<i32>,
+    trace_state: TraceState,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(machine: &'a BfMachine) -> Self {
+        Interpreter {
+            machine,
+            jump_table: build_jump_table(&machine.instructions.list),
+            memory: vec![0i64; machine.cache_size as usize],
+            index: 0,
+            pc: 0,
+            result: None,
+            trace_state: TraceState::new(),
+        }
+    }
+
+    pub fn machine(&self) -> &'a BfMachine {
+        self.machine
+    }
+
+    /// Index of the next instruction
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Index of the current cell
+    pub fn pointer(&self) -> u32 {
+        self.index
+    }
+
+    pub fn memory(&self) -> &[i64] {
+        &self.memory
+    }
+
+    /// The result of the program once it has terminated
+    pub fn result(&self) -> Option<i32> {
+        self.result
+    }
+
+    /// Index of the `EndLoop` matching the `BeginLoop` at `pc`
+    pub fn loop_end(&self, pc: usize) -> Option<usize> {
+        match self.machine.instructions.list.get(pc) {
+            Some(&BfInstruction::BeginLoop) => Some(self.jump_table[pc]),
+            _ => None,
+        }
+    }
+
+    /// Executes the next instruction.
+    ///
+    /// Terminates the program after its last instruction; once terminated every call returns the
+    /// same result without executing anything.
+    pub fn step<R: Read, W: Write, T: Write>(
+        &mut self,
+        input: &mut R,
+        output: &mut W,
+        trace: &mut T,
+    ) -> io::Result<Step> {
+
+        if let Some(result) = self.result {
+            return Ok(Step::Finished(result));
+        }
+
+        let len = self.machine.instructions.list.len();
+        if self.pc < len && !self.execute(input, output, trace)? {
+            output.flush()?;
+            self.result = Some(-1);
+            return Ok(Step::Finished(-1));
+        }
+
+        if self.pc >= len {
+            output.flush()?;
+            let result = *cell(&mut self.memory, self.index)? as i32;
+            self.result = Some(result);
+            return Ok(Step::Finished(result));
+        }
+
+        Ok(Step::Running)
+    }
+
+    /// Executes the instruction at `pc`; returns `false` if the program has to be aborted.
+    fn execute<R: Read, W: Write, T: Write>(
+        &mut self,
+        input: &mut R,
+        output: &mut W,
+        trace: &mut T,
+    ) -> io::Result<bool> {
+
+        let machine = self.machine;
+        let cell_bits = machine.cell_bits;
+        let memory = &mut self.memory;
+        let index = &mut self.index;
+        let pc = self.pc;
+
+        // NOTE(jpg): evaluates to the checked index of ptr[offset], returns if the check fails
+        macro_rules! target_index {
+            ($offset:expr) => {{
+                let offset: i64 = $offset;
+                if offset == 0 {
+                    *index
+                } else {
+                    let target = index.wrapping_add(offset as u32);
+                    match check_index(machine, memory, target) {
+                        Some(target) => target,
+                        None => return Ok(false),
+                    }
+                }
+            }}
+        }
+
+        match machine.instructions.list[pc] {
+
+            BfInstruction::SetValue(offset, value) => {
+                let target = target_index!(offset);
+                *cell(memory, target)? = truncate(value, cell_bits);
+            }
+
+            BfInstruction::AddValue(offset, value) => {
+                let target = target_index!(offset);
+                let cell = cell(memory, target)?;
+                *cell = truncate(cell.wrapping_add(value), cell_bits);
+            }
+
+            BfInstruction::AddPointer(value) => {
+                // NOTE(jpg): same 32 bit arithmetic as the generated code
+                match check_index(machine, memory, index.wrapping_add(value as u32)) {
+                    Some(new_index) => *index = new_index,
+                    None => return Ok(false),
+                }
+            }
+
+            BfInstruction::MulAdd { offset, factor } => {
+                let value = *cell(memory, *index)?;
+                if value != 0 {
+                    let target = target_index!(offset);
+                    let cell = cell(memory, target)?;
+                    *cell = truncate(cell.wrapping_add(value.wrapping_mul(factor)), cell_bits);
+                }
+            }
+
+            BfInstruction::ScanZero(stride) => {
+                while *cell(memory, *index)? != 0 {
+                    match check_index(machine, memory, index.wrapping_add(stride as u32)) {
+                        Some(new_index) => *index = new_index,
+                        None => return Ok(false),
+                    }
+                }
+            }
+
+            BfInstruction::Input(offset) => {
+                let target = target_index!(offset);
+                output.flush()?;
+                let mut buf = [0u8];
+                let cell = cell(memory, target)?;
+                *cell = match input.read(&mut buf)? {
+                    0 => {
+                        match machine.eof {
+                            EofBehaviour::Unchanged => *cell,
+                            EofBehaviour::Zero => 0,
+                            EofBehaviour::MinusOne => -1,
+                        }
+                    }
+                    _ => truncate(buf[0] as i64, cell_bits),
+                };
+            }
+
+            BfInstruction::Output(offset) => {
+                let target = target_index!(offset);
+                let value = *cell(memory, target)?;
+                output.write_all(&[value as u8])?;
+            }
+
+            BfInstruction::BeginLoop => {
+                if *cell(memory, *index)? == 0 {
+                    self.pc = self.jump_table[pc];
+                }
+            }
+
+            BfInstruction::EndLoop => {
+                self.pc = self.jump_table[pc];
+                return Ok(true);
+            }
+
+            BfInstruction::DebugLog => {
+                let record = TraceRecord {
+                    insn: pc as u32 + 1,
+                    pointer: *index as i32,
+                    position: machine.instructions.position(pc),
+                    cells: self.trace_state.changed_cells(memory),
+                };
+                writeln!(trace, "{}", record)?;
+            }
+        }
+
+        self.pc += 1;
+        Ok(true)
+    }
+}
+
+/// Applies the memory check to a new index, like `compiler::compile`.
+///
+/// Returns `None` if the program has to be aborted.
+fn check_index(machine: &BfMachine, memory: &mut Vec<i64>, index: u32) -> Option<u32> {
+    let cache_size = memory.len() as u32;
+
+    match machine.memory_overflow {
+        Undefined => Some(index),
+        Wrap => Some(index % cache_size),
+        Abort => if index < cache_size { Some(index) } else { None },
+        Grow => {
+            if index < cache_size {
+                return Some(index);
+            }
+            // NOTE(jpg): like a failed realloc in the generated code, a failed allocation aborts
+            // the program instead of the process
+            let new_size = index as usize * 2;
+            if index > machine.max_grow_index() ||
+                memory.try_reserve_exact(new_size - memory.len()).is_err()
+            {
+                return None;
+            }
+            memory.resize(new_size, 0);
+            Some(index)
+        }
+    }
+}
+
+/// Maps every `BeginLoop` to its `EndLoop` and every `EndLoop` to its `BeginLoop`.
+///
+/// `InstructionList` guarantees balanced loops, so every `EndLoop` has its `BeginLoop`.
+fn build_jump_table(insns: &[BfInstruction]) -> Vec<usize> {
+    let mut jump_table = vec![0; insns.len()];
+    let mut loop_stack = Vec::new();
+
+    for (i, insn) in insns.iter().enumerate() {
+        match *insn {
+            BfInstruction::BeginLoop => {
+                jump_table[i] = insns.len();
+                loop_stack.push(i);
+            }
+            BfInstruction::EndLoop => {
+                let begin = loop_stack.pop().expect(
+                    "unmatched 'EndLoop', InstructionList guarantees balanced loops",
+                );
+                jump_table[begin] = i;
+                jump_table[i] = begin;
+            }
+            _ => {} // no op
+        }
+    }
+
+    jump_table
+}
+
+/// Wraps `value` to a signed integer with `bits` bits, like the cell type of the generated code.
+fn truncate(value: i64, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    (value << shift) >> shift
+}
+
+fn cell(memory: &mut [i64], index: u32) -> io::Result<&mut i64> {
+    // NOTE(jpg): with 'undefined' memory checks the compiled program would access memory out of
+    // bounds; there is no sensible way to mimic that, so report it as an error instead.
+    memory.get_mut(index as usize).ok_or_else(|| {
+        io::Error::other(format!("memory access out of bounds at index {}", index as i32))
+    })
+}
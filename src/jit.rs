@@ -0,0 +1,101 @@
+use std::cell::Cell;
+use std::ffi::c_void;
+use std::ptr;
+
+use llvm::{LLVMString, Module};
+
+/// Exit code and captured output of `run`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JitOutput {
+    /// Value returned by the brainfuck function; `-1` if the program was aborted
+    pub exit_code: i32,
+    pub output: Vec<u8>,
+}
+
+/// The callbacks of the JIT call currently running on this thread
+struct Callbacks<'a> {
+    getchar: &'a mut dyn FnMut() -> i32,
+    putchar: &'a mut dyn FnMut(u8),
+}
+
+thread_local! {
+    static CALLBACKS: Cell<*mut Callbacks<'static>> = const { Cell::new(ptr::null_mut()) };
+}
+
+/// Executes `function_name` of a module created by `compiler::compile`, feeding `input` to
+/// `getchar` and capturing everything passed to `putchar`.
+pub fn run(
+    module: &Module,
+    function_name: LLVMString,
+    input: &[u8],
+) -> Result<JitOutput, String> {
+    let mut input = input.iter();
+    let mut output = Vec::new();
+
+    let exit_code = run_with_callbacks(
+        module,
+        function_name,
+        || input.next().map_or(-1, |&chr| chr as i32),
+        |chr| output.push(chr),
+    )?;
+
+    Ok(JitOutput { exit_code, output })
+}
+
+/// Executes `function_name` of a module created by `compiler::compile`, redirecting its
+/// `getchar` and `putchar` calls to the given closures.
+///
+/// `getchar` returns the next input byte, or `-1` on end of input.
+pub fn run_with_callbacks<G, P>(
+    module: &Module,
+    function_name: LLVMString,
+    mut getchar: G,
+    mut putchar: P,
+) -> Result<i32, String>
+where
+    G: FnMut() -> i32,
+    P: FnMut(u8),
+{
+    let mut callbacks = Callbacks {
+        getchar: &mut getchar,
+        putchar: &mut putchar,
+    };
+
+    // NOTE(jpg): the callbacks only live for this call, the guard resets the pointer even if
+    // the JIT call fails
+    let previous = CALLBACKS.with(|current| {
+        let callbacks: *mut Callbacks = &mut callbacks;
+        current.replace(callbacks.cast())
+    });
+    let _guard = ResetCallbacks(previous);
+
+    let mappings = [
+        (llvm_str!(b"getchar\0"), jit_getchar as extern "C" fn() -> i32 as *const c_void),
+        (llvm_str!(b"putchar\0"), jit_putchar as extern "C" fn(i32) -> i32 as *const c_void),
+    ];
+    module.jit_function(function_name, &mappings)
+}
+
+struct ResetCallbacks(*mut Callbacks<'static>);
+impl Drop for ResetCallbacks {
+    fn drop(&mut self) {
+        CALLBACKS.with(|current| current.set(self.0));
+    }
+}
+
+fn with_callbacks<T, F: FnOnce(&mut Callbacks) -> T>(f: F) -> T {
+    CALLBACKS.with(|current| {
+        let callbacks = current.get();
+        assert!(!callbacks.is_null(), "JIT callback called outside of 'jit::run'");
+        f(unsafe { &mut *callbacks })
+    })
+}
+
+extern "C" fn jit_getchar() -> i32 {
+    with_callbacks(|callbacks| (callbacks.getchar)())
+}
+
+extern "C" fn jit_putchar(chr: i32) -> i32 {
+    with_callbacks(|callbacks| (callbacks.putchar)(chr as u8));
+    chr
+}
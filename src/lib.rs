@@ -0,0 +1,44 @@
+extern crate tempfile;
+
+pub struct ParseEnumError {}
+
+#[macro_export]
+macro_rules! derive_FromStr {
+	($enum:path, {$( $enum_value:path: $str_val:expr ),*}) => {
+		impl ::std::str::FromStr for $enum {
+			type Err = $crate::ParseEnumError;
+			fn from_str(s: &str) -> Result<Self, Self::Err> {
+				match s {
+					$(
+						$str_val => Ok($enum_value),
+					)*
+					_ => Err( $crate::ParseEnumError {} ),
+				}
+			}
+		}
+	}
+}
+
+// NOTE(jpg): the LLVM wrappers pass raw pointers around; only `driver` exposes them safely
+#[macro_use]
+mod llvm;
+pub mod bf;
+mod compiler;
+pub mod interpreter;
+pub mod debugger;
+mod jit;
+pub mod driver;
+pub mod error;
+pub mod trace;
+
+#[cfg(test)]
+mod test;
+
+pub use bf::{BfInstruction, BfMachine, InstructionList, PassManager};
+pub use bf::{DebugPlacement, EofBehaviour, MemoryOverflowBehaviour};
+pub use debugger::Debugger;
+pub use driver::{Compiler, FileType, LinkOptions, Program};
+pub use error::CompileError;
+pub use jit::JitOutput;
+pub use llvm::{OptLevel, Target};
+pub use trace::TraceRecord;
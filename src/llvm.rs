@@ -1,15 +1,117 @@
 pub extern crate llvm_sys as sys;
 
 use std::{mem, ptr, ffi, str};
+use std::cell::Cell;
 
-use self::sys::LLVMIntPredicate;
+use self::sys::{LLVMIntPredicate, LLVMLinkage};
 use self::sys::prelude::*;
 use self::sys::core::*;
 use self::sys::execution_engine::*;
 use self::sys::target::*;
 use self::sys::analysis::*;
+use self::sys::bit_writer::*;
 use self::sys::transforms::pass_manager_builder::*;
 use self::sys::target_machine::*;
+use self::debug_info_sys::*;
+
+// NOTE(jpg): llvm-sys 38 predates the DIBuilder C API, the functions are declared by hand with
+// the signatures of LLVM 14, the minimum version checked by build.rs
+#[allow(non_snake_case)]
+mod debug_info_sys {
+    use std::os::raw::{c_char, c_int, c_uint};
+    use super::sys::prelude::*;
+
+    pub enum LLVMOpaqueMetadata {}
+    pub enum LLVMOpaqueDIBuilder {}
+    pub type LLVMMetadataRef = *mut LLVMOpaqueMetadata;
+    pub type LLVMDIBuilderRef = *mut LLVMOpaqueDIBuilder;
+
+    // NOTE(jpg): values of the C enums, not the DWARF constants
+    pub const LLVM_DWARF_SOURCE_LANGUAGE_C: c_uint = 1;
+    pub const LLVM_DWARF_EMISSION_FULL: c_uint = 1;
+    pub const LLVM_DI_FLAG_ZERO: c_int = 0;
+    pub const LLVM_MODULE_FLAG_BEHAVIOR_WARNING: c_uint = 1;
+
+    extern "C" {
+        pub fn LLVMDebugMetadataVersion() -> c_uint;
+        pub fn LLVMAddModuleFlag(
+            M: LLVMModuleRef,
+            Behavior: c_uint,
+            Key: *const c_char,
+            KeyLen: usize,
+            Val: LLVMMetadataRef,
+        );
+        pub fn LLVMValueAsMetadata(Val: LLVMValueRef) -> LLVMMetadataRef;
+
+        pub fn LLVMCreateDIBuilder(M: LLVMModuleRef) -> LLVMDIBuilderRef;
+        pub fn LLVMDisposeDIBuilder(Builder: LLVMDIBuilderRef);
+        pub fn LLVMDIBuilderFinalize(Builder: LLVMDIBuilderRef);
+        pub fn LLVMDIBuilderCreateFile(
+            Builder: LLVMDIBuilderRef,
+            Filename: *const c_char,
+            FilenameLen: usize,
+            Directory: *const c_char,
+            DirectoryLen: usize,
+        ) -> LLVMMetadataRef;
+        pub fn LLVMDIBuilderCreateCompileUnit(
+            Builder: LLVMDIBuilderRef,
+            Lang: c_uint,
+            FileRef: LLVMMetadataRef,
+            Producer: *const c_char,
+            ProducerLen: usize,
+            isOptimized: LLVMBool,
+            Flags: *const c_char,
+            FlagsLen: usize,
+            RuntimeVer: c_uint,
+            SplitName: *const c_char,
+            SplitNameLen: usize,
+            Kind: c_uint,
+            DWOId: c_uint,
+            SplitDebugInlining: LLVMBool,
+            DebugInfoForProfiling: LLVMBool,
+            SysRoot: *const c_char,
+            SysRootLen: usize,
+            SDK: *const c_char,
+            SDKLen: usize,
+        ) -> LLVMMetadataRef;
+        pub fn LLVMDIBuilderCreateSubroutineType(
+            Builder: LLVMDIBuilderRef,
+            File: LLVMMetadataRef,
+            ParameterTypes: *mut LLVMMetadataRef,
+            NumParameterTypes: c_uint,
+            Flags: c_int,
+        ) -> LLVMMetadataRef;
+        pub fn LLVMDIBuilderCreateFunction(
+            Builder: LLVMDIBuilderRef,
+            Scope: LLVMMetadataRef,
+            Name: *const c_char,
+            NameLen: usize,
+            LinkageName: *const c_char,
+            LinkageNameLen: usize,
+            File: LLVMMetadataRef,
+            LineNo: c_uint,
+            Ty: LLVMMetadataRef,
+            IsLocalToUnit: LLVMBool,
+            IsDefinition: LLVMBool,
+            ScopeLine: c_uint,
+            Flags: c_int,
+            IsOptimized: LLVMBool,
+        ) -> LLVMMetadataRef;
+        pub fn LLVMSetSubprogram(Func: LLVMValueRef, SP: LLVMMetadataRef);
+        pub fn LLVMDIBuilderCreateDebugLocation(
+            Ctx: LLVMContextRef,
+            Line: c_uint,
+            Column: c_uint,
+            Scope: LLVMMetadataRef,
+            InlinedAt: LLVMMetadataRef,
+        ) -> LLVMMetadataRef;
+        pub fn LLVMSetCurrentDebugLocation2(Builder: LLVMBuilderRef, Loc: LLVMMetadataRef);
+
+        pub fn LLVMCreateTargetDataLayout(
+            T: super::sys::target_machine::LLVMTargetMachineRef,
+        ) -> super::sys::target::LLVMTargetDataRef;
+    }
+}
 
 pub type LLVMString = *const i8;
 pub type Value = LLVMValueRef;
@@ -45,6 +147,85 @@ pub struct Module {
     pub i1_type: Type,
     pub i8_type: Type,
     pub i32_type: Type,
+
+    /// Debug location of builders created for this module, see `set_debug_location`
+    debug_location: Cell<LLVMMetadataRef>,
+}
+
+/// Target machine to generate code for; the default is the host with its default CPU
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Target {
+    /// Target triple, e.g. `aarch64-unknown-linux-gnu`; the host triple if `None`
+    pub triple: Option<String>,
+    pub cpu: String,
+    /// Comma separated feature string, e.g. `+sse4.2,-avx`
+    pub features: String,
+}
+
+/// Optimization level of the LLVM passes and of the code generator, like clang's `-O` option
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OptLevel {
+    O0,
+    O1,
+    O2,
+    O3,
+    /// Like `O2`, but optimizes for size
+    Os,
+    /// Like `Os`, but optimizes for size more aggressively
+    Oz,
+}
+
+derive_FromStr!(OptLevel, {
+    OptLevel::O0: "0",
+    OptLevel::O1: "1",
+    OptLevel::O2: "2",
+    OptLevel::O3: "3",
+    OptLevel::Os: "s",
+    OptLevel::Oz: "z"
+});
+
+impl OptLevel {
+    /// Speed level of the pass manager builder: 0 to 3
+    pub fn speed_level(self) -> u32 {
+        match self {
+            OptLevel::O0 => 0,
+            OptLevel::O1 => 1,
+            OptLevel::O2 | OptLevel::Os | OptLevel::Oz => 2,
+            OptLevel::O3 => 3,
+        }
+    }
+
+    /// Size level of the pass manager builder: 0, 1 for `Os` and 2 for `Oz`
+    pub fn size_level(self) -> u32 {
+        match self {
+            OptLevel::Os => 1,
+            OptLevel::Oz => 2,
+            _ => 0,
+        }
+    }
+
+    /// Inliner threshold, `None` if only `alwaysinline` functions are inlined
+    pub fn inline_threshold(self) -> Option<u32> {
+        // NOTE(jpg): same thresholds as clang
+        match self {
+            OptLevel::O0 | OptLevel::O1 => None,
+            OptLevel::O2 => Some(225),
+            OptLevel::O3 => Some(250),
+            OptLevel::Os => Some(75),
+            OptLevel::Oz => Some(25),
+        }
+    }
+
+    fn codegen_level(self) -> LLVMCodeGenOptLevel {
+        match self {
+            OptLevel::O0 => LLVMCodeGenOptLevel::LLVMCodeGenLevelNone,
+            OptLevel::O1 => LLVMCodeGenOptLevel::LLVMCodeGenLevelLess,
+            OptLevel::O2 | OptLevel::Os | OptLevel::Oz => {
+                LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault
+            }
+            OptLevel::O3 => LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -62,11 +243,6 @@ pub struct Function {
     pub value: Value,
 }
 
-#[derive(Copy, Clone)]
-pub struct PhiNode {
-    pub value: Value,
-}
-
 impl Module {
     pub fn new(module_name: LLVMString) -> Self {
         unsafe {
@@ -86,10 +262,23 @@ impl Module {
                 i1_type,
                 i8_type,
                 i32_type,
+
+                debug_location: Cell::new(ptr::null_mut()),
             }
         }
     }
 
+    /// Sets the debug location of the instructions emitted by builders created afterwards;
+    /// `None` emits instructions without location.
+    pub fn set_debug_location(&self, location: Option<DebugLocation>) {
+        let location = location.map_or(ptr::null_mut(), |location| location.inner_location);
+        self.debug_location.set(location);
+    }
+
+    pub fn int_type(&self, bits: u32) -> Type {
+        unsafe { Type::new(LLVMIntTypeInContext(self.inner_context, bits)) }
+    }
+
     pub fn set_target(&self, target_triple: LLVMString) {
         unsafe {
             LLVMSetTarget(self.inner_module, target_triple);
@@ -103,15 +292,15 @@ impl Module {
         }
     }
 
+    /// Returns the target triple of the module; `None` if not set.
     pub fn get_target(&self) -> Option<String> {
         unsafe {
-            let target_triple_message = LLVMGetDefaultTargetTriple();
-            if target_triple_message.is_null() {
+            // NOTE(jpg): the string is owned by the module and must not be disposed
+            let target_triple = LLVMGetTarget(self.inner_module);
+            if target_triple.is_null() {
                 None
             } else {
-                let target_triple = from_llvm_string(target_triple_message);
-                LLVMDisposeMessage(target_triple_message);
-                target_triple.ok()
+                from_llvm_string(target_triple).ok().filter(|triple| !triple.is_empty())
             }
         }
     }
@@ -122,6 +311,26 @@ impl Module {
         arguments: &mut [Type],
         return_type: Type,
     ) -> Function {
+        self.declare_function(function_name, arguments, return_type, false)
+    }
+
+    /// Adds a function taking a variable number of arguments after `arguments`, like `printf`.
+    pub fn add_variadic_function(
+        &self,
+        function_name: LLVMString,
+        arguments: &mut [Type],
+        return_type: Type,
+    ) -> Function {
+        self.declare_function(function_name, arguments, return_type, true)
+    }
+
+    fn declare_function(
+        &self,
+        function_name: LLVMString,
+        arguments: &mut [Type],
+        return_type: Type,
+        is_variadic: bool,
+    ) -> Function {
 
         let mut args: Vec<_> = arguments.iter().map(|tp| tp.inner_type).collect();
 
@@ -130,39 +339,99 @@ impl Module {
                 return_type.inner_type,
                 args.as_mut_ptr(),
                 args.len() as u32,
-                0,
+                is_variadic as LLVMBool,
             );
             let func = LLVMAddFunction(self.inner_module, function_name, function_type);
             Function { value: func }
         }
     }
 
+    /// Adds a zero initialized global variable that is private to the module.
+    pub fn add_global(&self, tp: Type, name: LLVMString) -> Var {
+        unsafe {
+            let value_ptr = LLVMAddGlobal(self.inner_module, tp.inner_type, name);
+            LLVMSetInitializer(value_ptr, LLVMConstNull(tp.inner_type));
+            LLVMSetLinkage(value_ptr, LLVMLinkage::LLVMInternalLinkage);
+            Var { value_ptr }
+        }
+    }
+
     pub fn append_basic_block(&self, function: Function, block_name: LLVMString) -> BasicBlock {
         unsafe { LLVMAppendBasicBlockInContext(self.inner_context, function.value, block_name) }
     }
 
-    pub fn dump(&self) {
+    /// Returns the textual LLVM IR of the module.
+    pub fn print_to_string(&self) -> String {
+        unsafe {
+            let message = LLVMPrintModuleToString(self.inner_module);
+            let result = ffi::CStr::from_ptr(message).to_string_lossy().into_owned();
+            LLVMDisposeMessage(message);
+            result
+        }
+    }
+
+    /// Writes the textual LLVM IR of the module to the file at `path`.
+    #[allow(dead_code)] // NOTE(jpg): the CLI prints through the output writer instead
+    pub fn print_to_file(&self, path: &str) -> Result<(), String> {
         unsafe {
-            LLVMDumpModule(self.inner_module);
+            let path = ffi::CString::new(path).map_err(|_| {
+                "Cannot print module; path contains a nul byte".to_owned()
+            })?;
+
+            let mut error_message = ptr::null_mut();
+            let result =
+                LLVMPrintModuleToFile(self.inner_module, path.as_ptr(), &mut error_message);
+
+            if result != 0 {
+                let message = from_llvm_string(error_message).unwrap_or_else(|_| {
+                    "Cannot print module; original LLVM error message is no valid utf8".to_owned()
+                });
+                LLVMDisposeMessage(error_message);
+                return Err(message);
+            }
         }
+
+        Ok(())
     }
 
-    pub fn verify(&self) {
+    /// Checks the module for invalid IR; the error contains the verifier message.
+    pub fn verify(&self) -> Result<(), String> {
         unsafe {
-            let message = ptr::null_mut();
-            LLVMVerifyModule(
+            let mut message = ptr::null_mut();
+            let failed = LLVMVerifyModule(
                 self.inner_module,
-                LLVMVerifierFailureAction::LLVMPrintMessageAction,
-                message,
+                LLVMVerifierFailureAction::LLVMReturnStatusAction,
+                &mut message,
             );
+
+            let result = if failed == 0 {
+                Ok(())
+            } else if message.is_null() {
+                Err("module verification failed".to_owned())
+            } else {
+                Err(from_llvm_string(message).unwrap_or_else(|_| {
+                    "module verification failed; original LLVM error message is no valid utf8"
+                        .to_owned()
+                }))
+            };
+
+            if !message.is_null() {
+                LLVMDisposeMessage(message);
+            }
+
+            result
         }
     }
 
-    pub fn optimize(&self, opt_level: u32) {
+    pub fn optimize(&self, opt_level: OptLevel) {
         unsafe {
 
             let manager_builder = LLVMPassManagerBuilderCreate();
-            LLVMPassManagerBuilderSetOptLevel(manager_builder, opt_level);
+            LLVMPassManagerBuilderSetOptLevel(manager_builder, opt_level.speed_level());
+            LLVMPassManagerBuilderSetSizeLevel(manager_builder, opt_level.size_level());
+            if let Some(threshold) = opt_level.inline_threshold() {
+                LLVMPassManagerBuilderUseInlinerWithThreshold(manager_builder, threshold);
+            }
 
             let pass_manager = LLVMCreatePassManager();
             LLVMPassManagerBuilderPopulateModulePassManager(manager_builder, pass_manager);
@@ -173,69 +442,131 @@ impl Module {
         }
     }
 
-    pub fn jit_function<Ret>(&self, function_name: LLVMString) -> Ret {
+    /// Compiles the module with MCJIT and calls the function `function_name`.
+    ///
+    /// Calls to the functions named in `mappings` are redirected to the given addresses instead
+    /// of being resolved in the current process.
+    pub fn jit_function<Ret>(
+        &self,
+        function_name: LLVMString,
+        mappings: &[(LLVMString, *const ffi::c_void)],
+    ) -> Result<Ret, String> {
         unsafe {
             LLVMLinkInMCJIT();
             LLVM_InitializeNativeTarget();
             LLVM_InitializeNativeAsmPrinter();
 
-            let mut ee = mem::uninitialized();
-            let mut out = mem::zeroed();
-            LLVMCreateExecutionEngineForModule(&mut ee, self.inner_module, &mut out);
+            let mut ee = ptr::null_mut();
+            let mut error_message = ptr::null_mut();
+            let failed =
+                LLVMCreateExecutionEngineForModule(&mut ee, self.inner_module, &mut error_message);
+            if failed != 0 {
+                let message = from_llvm_string(error_message).unwrap_or_else(|_| {
+                    "Cannot create execution engine; original LLVM error message is no valid utf8"
+                        .to_owned()
+                });
+                LLVMDisposeMessage(error_message);
+                return Err(message);
+            }
+
+            for &(name, address) in mappings {
+                let function = LLVMGetNamedFunction(self.inner_module, name);
+                if !function.is_null() {
+                    LLVMAddGlobalMapping(ee, function, address as *mut _);
+                }
+            }
 
             let addr = LLVMGetFunctionAddress(ee, function_name);
-            let func: extern "C" fn() -> Ret = mem::transmute(addr);
-            let result = func();
+            let result = if addr == 0 {
+                Err("Cannot find function to execute".to_owned())
+            } else {
+                let func: extern "C" fn() -> Ret = mem::transmute(addr);
+                Ok(func())
+            };
 
+            // NOTE(jpg): the execution engine owns the module; take it back so it is not
+            // disposed twice
+            let mut module = ptr::null_mut();
+            let mut error_message = ptr::null_mut();
+            LLVMRemoveModule(ee, self.inner_module, &mut module, &mut error_message);
             LLVMDisposeExecutionEngine(ee);
 
             result
         }
     }
 
-    pub fn write_object_file(&self, path: &str) -> Result<(), String> {
-        unsafe {
+    /// Emits an object file for the target triple of the module, using CPU and features of
+    /// `target` and the code generator level of `opt_level`.
+    pub fn write_object_file(
+        &self,
+        path: &str,
+        target: &Target,
+        opt_level: OptLevel,
+    ) -> Result<(), String> {
+        self.emit_to_file(path, target, opt_level, LLVMCodeGenFileType::LLVMObjectFile)
+    }
 
-            LLVM_InitializeAllTargetInfos();
-            LLVM_InitializeAllTargets();
-            LLVM_InitializeAllTargetMCs();
-            LLVM_InitializeAllAsmParsers();
-            LLVM_InitializeAllAsmPrinters();
+    /// Emits an assembly file like `write_object_file`.
+    pub fn write_assembly_file(
+        &self,
+        path: &str,
+        target: &Target,
+        opt_level: OptLevel,
+    ) -> Result<(), String> {
+        self.emit_to_file(path, target, opt_level, LLVMCodeGenFileType::LLVMAssemblyFile)
+    }
 
-            let target_triple = LLVMGetTarget(self.inner_module);
+    pub fn write_bitcode_file(&self, path: &str) -> Result<(), String> {
+        let result = unsafe { LLVMWriteBitcodeToFile(self.inner_module, to_llvm_string(path)) };
 
-            let mut target = ptr::null_mut();
-            let mut error_message = ptr::null_mut();
-            LLVMGetTargetFromTriple(target_triple, &mut target, &mut error_message);
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(format!("Cannot write bitcode file '{}'", path))
+        }
+    }
 
-            if !error_message.is_null() {
+    /// Sets the data layout of `target`, e.g. the size of pointers; fails for unknown targets.
+    pub fn set_data_layout(&self, target: &Target) -> Result<(), String> {
+        unsafe {
+            let target_machine = self.create_target_machine(target, OptLevel::O0)?;
+            let target_data = LLVMCreateTargetDataLayout(target_machine);
+            let data_layout = LLVMCopyStringRepOfTargetData(target_data);
+            LLVMSetDataLayout(self.inner_module, data_layout);
 
-                let error_message = from_llvm_string(error_message).map_err(|_| {
-                    "Cannot determine target tripple; original LLVM error message is no valid utf8"
-                        .to_owned()
-                })?;
+            LLVMDisposeMessage(data_layout);
+            LLVMDisposeTargetData(target_data);
+            LLVMDisposeTargetMachine(target_machine);
+        }
+        Ok(())
+    }
 
-                return Err(error_message);
-            }
+    /// Integer type with the size of a pointer in the data layout of the module, like `size_t`
+    pub fn int_ptr_type(&self) -> Type {
+        unsafe {
+            let target_data = LLVMCreateTargetData(LLVMGetDataLayout(self.inner_module));
+            let int_type = LLVMIntPtrTypeInContext(self.inner_context, target_data);
+            LLVMDisposeTargetData(target_data);
+            Type::new(int_type)
+        }
+    }
 
-            let cpu = llvm_str!(b"generic\0");
-            let features = llvm_str!(b"\0");
-            let target_machine = LLVMCreateTargetMachine(
-                target,
-                target_triple,
-                cpu,
-                features,
-                LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
-                LLVMRelocMode::LLVMRelocDefault,
-                LLVMCodeModel::LLVMCodeModelDefault,
-            );
+    fn emit_to_file(
+        &self,
+        path: &str,
+        target: &Target,
+        opt_level: OptLevel,
+        file_type: LLVMCodeGenFileType,
+    ) -> Result<(), String> {
+        unsafe {
+            let target_machine = self.create_target_machine(target, opt_level)?;
 
-            let mut error_message = to_llvm_string("asdasdasd");
+            let mut error_message = ptr::null_mut();
             let result = LLVMTargetMachineEmitToFile(
                 target_machine,
                 self.inner_module,
                 to_llvm_string(path),
-                LLVMCodeGenFileType::LLVMObjectFile,
+                file_type,
                 &mut error_message,
             );
 
@@ -243,7 +574,7 @@ impl Module {
 
             if result != 0 {
                 let error_message = from_llvm_string(error_message).map_err(|_| {
-                    "Cannot emit object file; original LLVM error message is no valid utf8"
+                    "Cannot emit file; original LLVM error message is no valid utf8"
                         .to_owned()
                 })?;
 
@@ -253,6 +584,46 @@ impl Module {
 
         Ok(())
     }
+
+    /// Creates a target machine for the target triple of the module; the caller disposes it.
+    unsafe fn create_target_machine(
+        &self,
+        target: &Target,
+        opt_level: OptLevel,
+    ) -> Result<LLVMTargetMachineRef, String> {
+        LLVM_InitializeAllTargetInfos();
+        LLVM_InitializeAllTargets();
+        LLVM_InitializeAllTargetMCs();
+        LLVM_InitializeAllAsmParsers();
+        LLVM_InitializeAllAsmPrinters();
+
+        let target_triple = LLVMGetTarget(self.inner_module);
+
+        let mut llvm_target = ptr::null_mut();
+        let mut error_message = ptr::null_mut();
+        LLVMGetTargetFromTriple(target_triple, &mut llvm_target, &mut error_message);
+
+        if !error_message.is_null() {
+
+            let error_message = from_llvm_string(error_message).map_err(|_| {
+                "Cannot determine target tripple; original LLVM error message is no valid utf8"
+                    .to_owned()
+            })?;
+
+            return Err(error_message);
+        }
+
+        Ok(LLVMCreateTargetMachine(
+            llvm_target,
+            target_triple,
+            to_llvm_string(target.cpu.as_str()),
+            to_llvm_string(target.features.as_str()),
+            opt_level.codegen_level(),
+            // NOTE(jpg): position independent, linkers default to PIE executables
+            LLVMRelocMode::LLVMRelocPIC,
+            LLVMCodeModel::LLVMCodeModelDefault,
+        ))
+    }
 }
 
 impl Drop for Module {
@@ -264,6 +635,144 @@ impl Drop for Module {
     }
 }
 
+/// Emits DWARF debug info for the functions of a module generated from a single source file
+pub struct DebugInfoBuilder {
+    inner_builder: LLVMDIBuilderRef,
+    inner_context: LLVMContextRef,
+    file: LLVMMetadataRef,
+    compile_unit: LLVMMetadataRef,
+}
+
+/// Subprogram created by `DebugInfoBuilder::create_function`
+#[derive(Copy, Clone)]
+pub struct DebugScope {
+    inner_scope: LLVMMetadataRef,
+}
+
+/// Source location of generated instructions
+#[derive(Copy, Clone)]
+pub struct DebugLocation {
+    inner_location: LLVMMetadataRef,
+}
+
+impl DebugInfoBuilder {
+    /// Creates the compile unit for `file_name`, which is relative to `directory` unless it is an
+    /// absolute path.
+    pub fn new(module: &Module, file_name: &str, directory: &str) -> Self {
+        unsafe {
+            let add_flag = |key: &str, value: u32| {
+                let value = LLVMConstInt(module.i32_type.inner_type, value as u64, 0);
+                LLVMAddModuleFlag(
+                    module.inner_module,
+                    LLVM_MODULE_FLAG_BEHAVIOR_WARNING,
+                    key.as_ptr() as *const i8,
+                    key.len(),
+                    LLVMValueAsMetadata(value),
+                );
+            };
+            add_flag("Debug Info Version", LLVMDebugMetadataVersion());
+            add_flag("Dwarf Version", 4);
+
+            let inner_builder = LLVMCreateDIBuilder(module.inner_module);
+            let file = LLVMDIBuilderCreateFile(
+                inner_builder,
+                file_name.as_ptr() as *const i8,
+                file_name.len(),
+                directory.as_ptr() as *const i8,
+                directory.len(),
+            );
+
+            // NOTE(jpg): there is no DWARF language code for brainfuck
+            let producer = "llvm-brainfuck";
+            let compile_unit = LLVMDIBuilderCreateCompileUnit(
+                inner_builder,
+                LLVM_DWARF_SOURCE_LANGUAGE_C,
+                file,
+                producer.as_ptr() as *const i8,
+                producer.len(),
+                0,
+                ptr::null(),
+                0,
+                0,
+                ptr::null(),
+                0,
+                LLVM_DWARF_EMISSION_FULL,
+                0,
+                0,
+                0,
+                ptr::null(),
+                0,
+                ptr::null(),
+                0,
+            );
+
+            DebugInfoBuilder {
+                inner_builder,
+                inner_context: module.inner_context,
+                file,
+                compile_unit,
+            }
+        }
+    }
+
+    /// Attaches a subprogram without parameters starting at `line` to `function`.
+    pub fn create_function(&self, function: Function, name: &str, line: u32) -> DebugScope {
+        unsafe {
+            let function_type = LLVMDIBuilderCreateSubroutineType(
+                self.inner_builder,
+                self.file,
+                ptr::null_mut(),
+                0,
+                LLVM_DI_FLAG_ZERO,
+            );
+            let inner_scope = LLVMDIBuilderCreateFunction(
+                self.inner_builder,
+                self.compile_unit,
+                name.as_ptr() as *const i8,
+                name.len(),
+                name.as_ptr() as *const i8,
+                name.len(),
+                self.file,
+                line,
+                function_type,
+                0,
+                1,
+                line,
+                LLVM_DI_FLAG_ZERO,
+                0,
+            );
+            LLVMSetSubprogram(function.value, inner_scope);
+            DebugScope { inner_scope }
+        }
+    }
+
+    pub fn location(&self, scope: DebugScope, line: u32, column: u32) -> DebugLocation {
+        let inner_location = unsafe {
+            LLVMDIBuilderCreateDebugLocation(
+                self.inner_context,
+                line,
+                column,
+                scope.inner_scope,
+                ptr::null_mut(),
+            )
+        };
+        DebugLocation { inner_location }
+    }
+
+    /// Completes the debug info; has to be called after all functions are generated.
+    pub fn finalize(&self) {
+        unsafe { LLVMDIBuilderFinalize(self.inner_builder) }
+    }
+}
+
+impl Drop for DebugInfoBuilder {
+    fn drop(&mut self) {
+        unsafe {
+            LLVMDisposeDIBuilder(self.inner_builder);
+        }
+    }
+}
+
 impl Type {
     pub fn new(inner_type: LLVMTypeRef) -> Self {
         Type { inner_type }
@@ -320,20 +829,30 @@ macro_rules! build_cast_op {
 }
 
 build_bin_op!(add, LLVMBuildAdd);
-build_bin_op!(udiv, LLVMBuildUDiv);
+build_bin_op!(sub, LLVMBuildSub);
+build_bin_op!(mul, LLVMBuildMul);
 build_bin_op!(urem, LLVMBuildURem);
-build_cast_op!(sext_or_bitcast, LLVMBuildSExtOrBitCast);
-build_cast_op!(trunc, LLVMBuildTrunc);
+build_bin_op!(ptr_diff, LLVMBuildPtrDiff);
+build_cast_op!(int_cast, LLVMBuildIntCast);
+build_cast_op!(bitcast, LLVMBuildBitCast);
 
 impl Builder {
     pub fn new(module: &Module, bb: BasicBlock) -> Self {
         unsafe {
             let inner_builder = LLVMCreateBuilderInContext(module.inner_context);
             LLVMPositionBuilderAtEnd(inner_builder, bb);
+            LLVMSetCurrentDebugLocation2(inner_builder, module.debug_location.get());
             Builder { inner_builder }
         }
     }
 
+    /// Sets the debug location of the instructions emitted afterwards, see
+    /// `Module::set_debug_location`.
+    pub fn set_debug_location(&self, location: Option<DebugLocation>) {
+        let location = location.map_or(ptr::null_mut(), |location| location.inner_location);
+        unsafe { LLVMSetCurrentDebugLocation2(self.inner_builder, location) }
+    }
+
     pub fn uint(&self, tp: Type, value: u64) -> Value {
         unsafe { LLVMConstInt(tp.inner_type, value, 0) }
     }
@@ -342,6 +861,10 @@ impl Builder {
         unsafe { LLVMConstInt(tp.inner_type, value as u64, 1) }
     }
 
+    pub fn null(&self, tp: Type) -> Value {
+        unsafe { LLVMConstNull(tp.inner_type) }
+    }
+
     pub fn call<RetV: StoreValue<R>, R>(
         &self,
         function: Function,
@@ -360,6 +883,12 @@ impl Builder {
         }
     }
 
+    /// Creates a private constant holding `value` with a terminating zero and returns an `i8*`
+    /// pointing to it.
+    pub fn global_string(&self, value: &str, name: LLVMString) -> Value {
+        unsafe { LLVMBuildGlobalStringPtr(self.inner_builder, to_llvm_string(value), name) }
+    }
+
     pub fn alloca(&self, tp: Type, name: LLVMString) -> Value {
         unsafe { LLVMBuildAlloca(self.inner_builder, tp.inner_type, name) }
     }
@@ -416,6 +945,25 @@ impl Builder {
         }
     }
 
+    pub fn select<CV: LoadValue, TV: LoadValue, EV: LoadValue, RetV: StoreValue<R>, R>(
+        &self,
+        if_value: CV,
+        then_value: TV,
+        else_value: EV,
+        result: RetV,
+    ) -> R {
+        unsafe {
+            let ret = LLVMBuildSelect(
+                self.inner_builder,
+                if_value.load_value(self),
+                then_value.load_value(self),
+                else_value.load_value(self),
+                result.get_name(),
+            );
+            result.store_value(self, ret)
+        }
+    }
+
     pub fn ret<V: LoadValue>(&self, value: V) {
         unsafe {
             LLVMBuildRet(self.inner_builder, value.load_value(self));
@@ -447,13 +995,6 @@ impl Builder {
             )
         }
     }
-
-    pub fn phi(&self, tp: Type, name: LLVMString) -> PhiNode {
-        unsafe {
-            let value = LLVMBuildPhi(self.inner_builder, tp.inner_type, name);
-            PhiNode { value }
-        }
-    }
 }
 
 impl Drop for Builder {
@@ -474,14 +1015,6 @@ impl Function {
     }
 }
 
-impl PhiNode {
-    pub fn add_incoming(&self, incoming_value: Value, incoming_block: BasicBlock) {
-        let mut values = vec![incoming_value];
-        let mut block = vec![incoming_block];
-        unsafe { LLVMAddIncoming(self.value, values.as_mut_ptr(), block.as_mut_ptr(), 1) }
-    }
-}
-
 #[derive(Clone, Copy)]
 pub struct Var {
     value_ptr: Value,
@@ -492,7 +1025,7 @@ impl Var {
         let value_ptr = builder.alloca(tp, name);
         let result = Var { value_ptr };
         result.store(builder, value);
-        return result;
+        result
     }
 
     pub fn load(&self, builder: &Builder) -> Value {
@@ -530,7 +1063,7 @@ impl StoreValue<Value> for LLVMString {
         *self
     }
     fn store_value<V: LoadValue>(&self, builder: &Builder, value: V) -> Value {
-        value.load_value(&builder)
+        value.load_value(builder)
     }
 }
 
@@ -539,7 +1072,7 @@ impl StoreValue<Var> for Var {
         llvm_str!(b"var_val\0")
     }
     fn store_value<V: LoadValue>(&self, builder: &Builder, value: V) -> Var {
-        self.store(&builder, value.load_value(&builder));
+        self.store(builder, value.load_value(builder));
         *self
     }
 }
@@ -548,7 +1081,5 @@ impl StoreValue<()> for () {
     fn get_name(&self) -> LLVMString {
         llvm_str!(b"\0")
     }
-    fn store_value<V: LoadValue>(&self, _builder: &Builder, _value: V) -> () {
-        ()
-    }
+    fn store_value<V: LoadValue>(&self, _builder: &Builder, _value: V) {}
 }
@@ -1,32 +1,63 @@
-extern crate tempfile;
-extern crate argparse;
-
 #[macro_use]
-mod llvm;
-mod bf;
-mod compiler;
-
-#[cfg(test)]
-mod test;
+extern crate llvm_brainfuck;
+extern crate argparse;
 
+use std::cell::RefCell;
 use std::fs;
-use std::io::{self, Write};
+use std::io::{self, Read, Write};
 
-use tempfile::NamedTempFile;
-use argparse::{ArgumentParser, StoreTrue, Store};
+use argparse::{ArgumentParser, StoreTrue, Store, StoreOption, Collect};
 
-use bf::{InstructionList, MemoryOverflowBehaviour, BfMachine};
-use compiler::compile;
+use llvm_brainfuck::{CompileError, Compiler, FileType, LinkOptions, Program};
+use llvm_brainfuck::{MemoryOverflowBehaviour, EofBehaviour, BfMachine, PassManager};
+use llvm_brainfuck::{DebugPlacement, Debugger};
+use llvm_brainfuck::bf::PASS_NAMES;
+use llvm_brainfuck::interpreter::interpret;
+use llvm_brainfuck::{OptLevel, Target};
 
 struct Config {
     verbose: bool,
     input: String,
+    input_format: InputFormat,
     output: String,
     force_binary_stdout: bool,
     output_format: OutputFormat,
     emit_debug: bool,
+    debug_at: DebugPlacement,
+    trace_fd: i32,
+    debug_info: bool,
     memory_check: MemoryOverflowBehaviour,
     memory_size: i64,
+    cell_bits: u32,
+    eof: EofBehaviour,
+    opt_level: OptLevel,
+    passes: Option<String>,
+    target_triple: String,
+    target_cpu: String,
+    target_features: String,
+    linker: String,
+    link_args: Vec<String>,
+    static_link: bool,
+}
+
+impl Config {
+    fn target(&self) -> Target {
+        Target {
+            triple: if self.target_triple.is_empty() {
+                None
+            } else {
+                Some(self.target_triple.clone())
+            },
+            cpu: self.target_cpu.clone(),
+            features: self.target_features.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum InputFormat {
+    Brainfuck,
+    BrainfuckIR,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -34,159 +65,250 @@ enum OutputFormat {
     BrainfuckIR,
     LlvmIRUnoptimized,
     LlvmIR,
+    Bitcode,
+    Assembly,
     ObjectFile,
     ExecutableFile,
     Run,
+    Interpret,
+    Debug,
 }
 
 impl OutputFormat {
     fn is_binary(self) -> bool {
-        self == OutputFormat::ObjectFile || self == OutputFormat::ExecutableFile
+        self == OutputFormat::Bitcode || self == OutputFormat::ObjectFile ||
+            self == OutputFormat::ExecutableFile
     }
 }
 
-pub struct ParseEnumError {}
-macro_rules! derive_FromStr {
-	($enum:path, {$( $enum_value:path: $str_val:expr ),*}) => {
-		impl std::str::FromStr for $enum {
-			type Err = ParseEnumError;
-			fn from_str(s: &str) -> Result<Self, Self::Err> {
-				match s {
-					$(
-						$str_val => Ok($enum_value),
-					)*
-					_ => Err( ParseEnumError {} ),
-				}
-			}
-		}
-	}
-}
+derive_FromStr!(InputFormat, {
+	InputFormat::Brainfuck: "bf",
+	InputFormat::BrainfuckIR: "bf-ir"
+});
 
 derive_FromStr!(OutputFormat, {
 	OutputFormat::BrainfuckIR: "bf-ir",
     OutputFormat::LlvmIRUnoptimized: "llvm-ir-unop",
     OutputFormat::LlvmIR: "llvm-ir",
+    OutputFormat::Bitcode: "bc",
+    OutputFormat::Assembly: "asm",
     OutputFormat::ObjectFile: "obj",
     OutputFormat::ExecutableFile: "exec",
-    OutputFormat::Run: "run"
-});
-
-derive_FromStr!(MemoryOverflowBehaviour, {
-	MemoryOverflowBehaviour::Undefined: "undefined",
-	MemoryOverflowBehaviour::Wrap: "wrap",
-	MemoryOverflowBehaviour::Abort: "abort"
+    OutputFormat::Run: "run",
+    OutputFormat::Interpret: "interp",
+    OutputFormat::Debug: "debug"
 });
 
 fn main() {
-    let cfg = parse_config_or_exit();
-    let result = run(cfg);
-    let code = match result {
+    let code = match parse_config().and_then(run) {
         Ok(code) => code,
-        Err(message) => {
-            eprintln!("{}", message);
-            4
+        Err(err) => {
+            eprintln!("{}", err);
+            err.exit_code()
         }
     };
     std::process::exit(code);
 }
 
-fn run(cfg: Config) -> Result<i32, String> {
+fn run(cfg: Config) -> Result<i32, CompileError> {
 
-    let input = read_input(cfg.input.as_str());
-    let mut output = create_output_writer(&cfg.output);
+    if cfg.output_format == OutputFormat::Debug && cfg.input.is_empty() {
+        return Err(CompileError::InvalidArgument(
+            "the debugger reads its commands from stdin; pass the source with -i".to_owned(),
+        ));
+    }
 
-    let machine = create_bf_machine(input, &cfg);
+    let input = read_input(cfg.input.as_str())?;
+    let mut output = create_output_writer(&cfg.output)?;
 
-    if cfg.output_format == OutputFormat::BrainfuckIR {
-        for item in machine.instructions.list.iter().enumerate() {
-            output.write_fmt(format_args!("{:?}\n", item)).unwrap();
-        }
-        return Ok(0);
-    }
+    let compiler = create_compiler(&cfg)?;
+    let machine = match cfg.input_format {
+        InputFormat::Brainfuck => compiler.parse(input.as_str())?,
+        InputFormat::BrainfuckIR => compiler.parse_ir(input.as_str())?,
+    };
 
-    let (module, function_name) = compile(&machine, true);
+    let compile = || compile_program(&compiler, &machine, &cfg);
 
-    if cfg.output_format == OutputFormat::LlvmIRUnoptimized {
-        module.dump(); // TODO(jpg): write this to output writer
-        return Ok(0);
+    match cfg.output_format {
+        OutputFormat::BrainfuckIR => {
+            output
+                .write_fmt(format_args!("{}", machine.instructions))
+                .and_then(|_| output.flush())
+                .map_err(|err| CompileError::io("failed to write brainfuck IR", err))?;
+            Ok(0)
+        }
+        OutputFormat::Interpret => run_interpreter(&machine, &mut output),
+        OutputFormat::Debug => run_debugger(&machine, &input, &mut output),
+        OutputFormat::LlvmIRUnoptimized | OutputFormat::LlvmIR => {
+            write_llvm_ir(&compile()?, &mut output)?;
+            Ok(0)
+        }
+        OutputFormat::Run => {
+            let result = run_jit(&compiler, &compile()?, &mut output)?;
+            check_aborted(result)
+        }
+        OutputFormat::Bitcode => {
+            compiler.emit(&compile()?, FileType::Bitcode, &mut output)?;
+            Ok(0)
+        }
+        OutputFormat::Assembly => {
+            compiler.emit(&compile()?, FileType::Assembly, &mut output)?;
+            Ok(0)
+        }
+        OutputFormat::ObjectFile => {
+            compiler.emit(&compile()?, FileType::Object, &mut output)?;
+            Ok(0)
+        }
+        OutputFormat::ExecutableFile => {
+            let exec_path = if cfg.output.is_empty() {
+                "./bf"
+            } else {
+                cfg.output.as_str()
+            }; // TODO: use output writer instead
+
+            compiler.write_executable(&compile()?, exec_path)?;
+            Ok(0)
+        }
     }
+}
 
-    module.verify(); // TODO(jpg): print error and exit
-    module.optimize(3);
+/// Generates the LLVM module; optimized unless unoptimized IR is requested.
+fn compile_program(
+    compiler: &Compiler,
+    machine: &BfMachine,
+    cfg: &Config,
+) -> Result<Program, CompileError> {
 
-    if cfg.output_format == OutputFormat::LlvmIR {
-        module.dump(); // TODO(jpg): write this to output writer
-        return Ok(0);
+    let program = compiler.codegen(machine);
+    if cfg.output_format == OutputFormat::LlvmIRUnoptimized {
+        return Ok(program);
     }
 
-    if cfg.output_format == OutputFormat::Run {
-        let result: i32 = module.jit_function(function_name);
+    compiler.optimize(&program).map_err(|err| match err {
+        CompileError::Verify(message) => {
+            CompileError::Verify(describe_invalid_module(&message, &program, cfg.verbose))
+        }
+        err => err,
+    })?;
+    Ok(program)
+}
 
-        return if result != -1 {
-            Ok(result)
-        } else {
-            Err("Error encountered during execution. Aborting!".to_owned())
-        };
+fn check_aborted(result: i32) -> Result<i32, CompileError> {
+    if result != -1 {
+        Ok(result)
+    } else {
+        Err(CompileError::Runtime("program aborted by memory check".to_owned()))
     }
+}
 
-    let obj_file = NamedTempFile::new().map_err(|_| {
-        "failed to create temporary object file".to_owned()
+fn run_interpreter<W: Write>(machine: &BfMachine, output: &mut W) -> Result<i32, CompileError> {
+    let stdin = io::stdin();
+    let result = interpret(machine, &mut stdin.lock(), output).map_err(|err| {
+        CompileError::Runtime(err.to_string())
     })?;
 
-    let obj_path = obj_file.path().to_str().ok_or(
-        "temporary object file name is not valid utf8"
-            .to_owned(),
-    )?;
-
-    module.write_object_file(obj_path)?;
+    check_aborted(result)
+}
 
-    if cfg.output_format == OutputFormat::ObjectFile {
-        // TODO(jpg): write this to output writer
-        return Ok(0);
-    }
+fn run_debugger<W: Write>(
+    machine: &BfMachine,
+    source: &str,
+    output: &mut W,
+) -> Result<i32, CompileError> {
+    let stdin = io::stdin();
+    let mut debugger = Debugger::new(machine, source, stdin.lock(), io::stdout(), output);
+    let result = debugger.run().map_err(|err| CompileError::Runtime(err.to_string()))?;
+    check_aborted(result.unwrap_or(0))
+}
 
-    let target_triple = module.get_target().ok_or(
-        "failed determine target triple"
-            .to_owned(),
-    )?;
+fn describe_invalid_module(message: &str, program: &Program, verbose: bool) -> String {
+    let mut result = format!("{}\n", message.trim_end());
 
-    let exec_path = if cfg.output == "" {
-        "./bf"
+    if verbose {
+        result.push_str("\ninvalid LLVM IR:\n");
+        result.push_str(&program.llvm_ir());
     } else {
-        cfg.output.as_str()
-    }; // TODO: use output writer instead
+        result.push_str("note: run with '-v' to print the invalid LLVM IR");
+    }
+    result
+}
 
-    link_object_file(obj_path, exec_path, target_triple.as_str())?;
+fn write_llvm_ir<W: Write>(program: &Program, output: &mut W) -> Result<(), CompileError> {
+    output
+        .write_all(program.llvm_ir().as_bytes())
+        .and_then(|_| output.flush())
+        .map_err(|err| CompileError::io("failed to write LLVM IR", err))
+}
 
-    if let Err(_) = fs::remove_file(obj_file.path()) {
-        // TODO(jpg): warning, object file could not be removed
-    }
+fn run_jit<W: Write>(
+    compiler: &Compiler,
+    program: &Program,
+    output: &mut W,
+) -> Result<i32, CompileError> {
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+
+    // NOTE(jpg): both callbacks use the output, the first write error is reported afterwards
+    let output = RefCell::new(output);
+    let write_error = RefCell::new(None);
+    let record_error = |result: io::Result<()>| if let Err(err) = result {
+        write_error.borrow_mut().get_or_insert(err);
+    };
 
-    if cfg.output_format == OutputFormat::ExecutableFile {
-        // TODO(jpg): write this to output writer
-        return Ok(0);
-    }
+    let result = compiler.run_with_callbacks(
+        program,
+        || {
+            record_error(output.borrow_mut().flush());
+            let mut buf = [0u8];
+            match stdin.read(&mut buf) {
+                Ok(1) => buf[0] as i32,
+                _ => -1,
+            }
+        },
+        |chr| record_error(output.borrow_mut().write_all(&[chr])),
+    )?;
 
-    panic!("Unexpected program state");
+    record_error(output.borrow_mut().flush());
+    match write_error.into_inner() {
+        Some(err) => Err(CompileError::io("failed to write output", err)),
+        None => Ok(result),
+    }
 }
 
-fn parse_config_or_exit() -> Config {
+fn parse_config() -> Result<Config, CompileError> {
 
     let mut cfg = Config {
         verbose: false,
         input: "".to_owned(),
+        input_format: InputFormat::Brainfuck,
         output: "".to_owned(),
         force_binary_stdout: false,
         output_format: OutputFormat::ExecutableFile,
         emit_debug: false,
+        debug_at: DebugPlacement::All,
+        trace_fd: 2,
+        debug_info: false,
         memory_check: MemoryOverflowBehaviour::Undefined,
         memory_size: 4096,
+        cell_bits: 8,
+        eof: EofBehaviour::MinusOne,
+        opt_level: OptLevel::O3,
+        passes: None,
+        target_triple: "".to_owned(),
+        target_cpu: "".to_owned(),
+        target_features: "".to_owned(),
+        linker: "".to_owned(),
+        link_args: Vec::new(),
+        static_link: false,
     };
 
     {
         let mut parser = ArgumentParser::new();
 
-        parser.set_description("Brainfuck compiler");
+        parser.set_description(
+            "Brainfuck compiler. Exit codes on errors: 1 invalid arguments, 3 I/O error,
+            4 parse error, 5 invalid LLVM module, 6 code generation, 7 linking, 8 execution",
+        );
         parser.refer(&mut cfg.verbose).add_option(
             &["-v", "--verbose"],
             StoreTrue,
@@ -197,6 +319,13 @@ fn parse_config_or_exit() -> Config {
             Store,
             "Input file; stdin if not set or empty.",
         );
+        parser.refer(&mut cfg.input_format).add_option(
+            &["--input-format"],
+            Store,
+            "Choose input format:
+				bf (default; brainfuck source code),
+				bf-ir (textual brainfuck IR as written by '-t bf-ir')",
+        );
         parser.refer(&mut cfg.output).add_option(
             &["-o", "--output"],
             Store,
@@ -214,14 +343,41 @@ fn parse_config_or_exit() -> Config {
 				bf-ir (optimized brainfuck IR),
 				llvm-ir-unop (unoptimized LLVM IR),
 				llvm-ir (optimized LLVM IR),
+				bc (optimized LLVM bitcode),
+				asm (native assembly),
 				obj (object file),
 				exec (default; executable file),
-				run (compiles and executes the given source)",
+				run (compiles and executes the given source),
+				interp (executes the given source without compiling it),
+				debug (interactive step debugger; reads commands from stdin, so the source has
+				to be given with -i; runs unoptimized brainfuck IR unless --passes is set)",
         );
         parser.refer(&mut cfg.emit_debug).add_option(
             &["-d", "--debug"],
+            StoreTrue,
+            "Write trace records with the pointer and the changed cells as JSON lines at the
+        		places selected by --debug-at and at '#' breakpoints; pretty-print with bf-trace",
+        );
+        parser.refer(&mut cfg.debug_at).add_option(
+            &["--debug-at"],
+            Store,
+            "Where -d writes trace records:
+        		all (default; before every instruction),
+        		loops (before every loop and at the start of every iteration),
+        		io (before every output and after every input),
+        		breakpoints (only at '#')",
+        );
+        parser.refer(&mut cfg.trace_fd).add_option(
+            &["--trace-fd"],
             Store,
-            "Emit debug calls",
+            "File descriptor the trace is written to; default 2 (stderr)",
+        );
+        parser.refer(&mut cfg.debug_info).add_option(
+            &["-g", "--debug-info"],
+            StoreTrue,
+            "Emit DWARF debug info with brainfuck source lines; optimized instructions refer
+        		to the first character folded into them, combine with -O0 to step through every
+        		instruction",
         );
         parser.refer(&mut cfg.memory_check).add_option(
             &["-m", "--mem-check"],
@@ -229,106 +385,183 @@ fn parse_config_or_exit() -> Config {
             "Memory check:
         		undefined (default, no memory check is performed),
         		abort (program aborts on invalid memory access),
-        		wrap (memory pointer wraps on invalid memory access)",
+        		wrap (memory pointer wraps on invalid memory access),
+        		grow (memory grows when the pointer moves past its end)",
         );
         parser.refer(&mut cfg.memory_size).add_option(
             &["-s", "--mem-size"],
             Store,
             "Initial memory size. Default: 4096",
         );
+        parser.refer(&mut cfg.cell_bits).add_option(
+            &["-c", "--cell-bits"],
+            Store,
+            "Width of a memory cell in bits: 8 (default), 16, 32 or 64",
+        );
+        parser.refer(&mut cfg.eof).add_option(
+            &["-e", "--eof"],
+            Store,
+            "Value stored by ',' on end of input:
+        		minus-one (default, cell is set to -1),
+        		zero (cell is set to 0),
+        		unchanged (cell keeps its value)",
+        );
+        parser.refer(&mut cfg.opt_level).add_option(
+            &["-O", "--opt-level"],
+            Store,
+            "Optimization level of the brainfuck IR passes, the LLVM passes and the code
+        		generator: 0, 1, 2, 3 (default), s (optimize for size), z (minimize size)",
+        );
+        parser.refer(&mut cfg.passes).add_option(
+            &["--passes"],
+            StoreOption,
+            "Comma separated list of brainfuck IR passes to run instead of the ones chosen by
+        		the optimization level; an empty list runs none",
+        );
+
+        parser.refer(&mut cfg.target_triple).add_option(
+            &["--target"],
+            Store,
+            "Target triple, e.g. aarch64-unknown-linux-gnu; host if not set or empty",
+        );
+        parser.refer(&mut cfg.target_cpu).add_option(
+            &["--cpu"],
+            Store,
+            "Target CPU, e.g. cortex-a72; default CPU of the target if not set or empty",
+        );
+        parser.refer(&mut cfg.target_features).add_option(
+            &["--features"],
+            Store,
+            "Comma separated target features, e.g. +sse4.2,-avx",
+        );
+
+        parser.refer(&mut cfg.linker).add_option(
+            &["--linker"],
+            Store,
+            "Linker used for executables: clang, cc, gcc, ld or a cross variant like
+				aarch64-linux-gnu-gcc. Default: $BF_LINKER, or clang if not set",
+        );
+        parser.refer(&mut cfg.link_args).add_option(
+            &["--link-arg"],
+            Collect,
+            "Additional linker argument; repeatable, e.g. --link-arg=-lm",
+        );
+        parser.refer(&mut cfg.static_link).add_option(
+            &["--static"],
+            StoreTrue,
+            "Link a static executable",
+        );
 
-        parser.parse_args_or_exit();
+        // NOTE(jpg): argparse has already printed the error, it would exit with 2 on its own
+        if let Err(code) = parser.parse_args() {
+            let error = CompileError::InvalidArgument(String::new());
+            std::process::exit(if code == 0 { 0 } else { error.exit_code() });
+        }
     }
 
-    if !cfg.force_binary_stdout && cfg.output_format.is_binary() && cfg.output == "" {
-        eprintln!("Writing binary to stdout is disabled, force with flag'-f'.");
-        std::process::exit(1);
+    if !cfg.force_binary_stdout && cfg.output_format.is_binary() && cfg.output.is_empty() {
+        return Err(CompileError::InvalidArgument(
+            "Writing binary to stdout is disabled, force with flag'-f'.".to_owned(),
+        ));
     }
 
     if cfg.memory_size < 1 {
-        eprintln!("Invalid memory size. Must be positive");
-        std::process::exit(1);
+        return Err(CompileError::InvalidArgument(
+            "Invalid memory size. Must be positive".to_owned(),
+        ));
+    }
+
+    if ![8, 16, 32, 64].contains(&cfg.cell_bits) {
+        return Err(CompileError::InvalidArgument(
+            "Invalid cell width. Must be one of 8, 16, 32 or 64".to_owned(),
+        ));
     }
 
-    cfg
+    Ok(cfg)
 }
 
-fn create_bf_machine(source: String, cfg: &Config) -> BfMachine {
+fn create_compiler(cfg: &Config) -> Result<Compiler, CompileError> {
+
+    let mut compiler = Compiler::new().opt_level(cfg.opt_level);
 
-    let mut insns = InstructionList::from_chars(source.chars());
-    if cfg.emit_debug {
-        insns.insert_debug_logs();
+    // NOTE(jpg): the debugger steps through every instruction of the source code
+    if cfg.output_format == OutputFormat::Debug {
+        compiler = compiler.pass_manager(PassManager::new());
     }
 
-    BfMachine {
-        cache_size: cfg.memory_size,
-        instructions: insns,
-        memory_overflow: cfg.memory_check,
+    if let Some(ref passes) = cfg.passes {
+        let names: Vec<_> =
+            passes.split(',').map(|name| name.trim()).filter(|name| !name.is_empty()).collect();
+        let pass_manager = PassManager::with_pass_names(&names).map_err(|err| {
+            CompileError::InvalidArgument(
+                format!("{}; available passes: {}", err, PASS_NAMES.join(", ")),
+            )
+        })?;
+        compiler = compiler.pass_manager(pass_manager);
     }
+
+    if cfg.verbose {
+        eprintln!("brainfuck IR passes: {:?}", compiler.pass_names());
+    }
+
+    if cfg.debug_info {
+        let source_path = if cfg.input.is_empty() { "<stdin>" } else { cfg.input.as_str() };
+        compiler = compiler.debug_info(source_path);
+    }
+
+    Ok(compiler
+        .memory_size(cfg.memory_size)
+        .cell_bits(cfg.cell_bits)
+        .memory_overflow(cfg.memory_check)
+        .eof(cfg.eof)
+        .emit_debug(cfg.emit_debug)
+        .debug_at(cfg.debug_at)
+        .trace_fd(cfg.trace_fd)
+        .target(cfg.target())
+        .link_options(LinkOptions {
+            linker: if cfg.linker.is_empty() {
+                None
+            } else {
+                Some(cfg.linker.clone())
+            },
+            args: cfg.link_args.clone(),
+            static_link: cfg.static_link,
+        })
+        .verbose(cfg.verbose))
 }
 
-fn read_input(input_file_option: &str) -> String {
-    let input: Box<io::Read> = if input_file_option == "" {
+fn read_input(input_file_option: &str) -> Result<String, CompileError> {
+    let mut input: Box<dyn io::Read> = if input_file_option.is_empty() {
         let stdin = io::stdin();
         // TODO(jpg): stdin.lock() ???
         Box::new(stdin)
     } else {
-        let input_file = fs::File::open(input_file_option).expect("Could not open input file");
+        let input_file = fs::File::open(input_file_option).map_err(|err| {
+            CompileError::io(format!("Could not open input file '{}'", input_file_option), err)
+        })?;
         Box::new(input_file)
     };
 
-    let mut input = io::BufReader::new(input);
-    read_text(&mut input)
+    let mut result = String::new();
+    input.read_to_string(&mut result).map_err(|err| {
+        CompileError::io("error while reading", err)
+    })?;
+    Ok(result)
 }
 
-fn create_output_writer(output_file_option: &String) -> io::BufWriter<Box<io::Write>> {
+fn create_output_writer(
+    output_file_option: &String,
+) -> Result<io::BufWriter<Box<dyn io::Write>>, CompileError> {
 
-    let output: Box<io::Write> = if output_file_option == "" {
+    let output: Box<dyn io::Write> = if output_file_option.is_empty() {
         let stdout = io::stdout();
         Box::new(stdout)
     } else {
-        let output_file =
-            fs::File::create(output_file_option).expect("Could not create output file");
+        let output_file = fs::File::create(output_file_option).map_err(|err| {
+            CompileError::io(format!("Could not create output file '{}'", output_file_option), err)
+        })?;
         Box::new(output_file)
     };
 
-    io::BufWriter::new(output)
-}
-
-fn read_text<R: io::BufRead + ?Sized>(r: &mut R) -> String {
-    let error_msg = "error while reading";
-    let mut buff = Vec::new();
-    let mut result = String::new();
-
-    while r.read_until(b'\n', &mut buff).expect(error_msg) != 0 {
-        let s = String::from_utf8(buff).expect(error_msg);
-        result.push_str(s.as_str());
-        buff = s.into_bytes();
-        buff.clear();
-    }
-
-    return result;
-}
-
-fn link_object_file(obj_path: &str, exec_path: &str, target_triple: &str) -> Result<(), String> {
-
-    let arguments = vec![obj_path, "-o", exec_path, "-target", target_triple];
-    println!("clang args: {:?}", arguments);
-
-    let status = std::process::Command::new("clang")
-        .args(arguments)
-        .status()
-        .map_err(|_| "failed to execute clang")?;
-
-    if status.success() {
-        Ok(())
-    } else {
-        match status.code() {
-            Some(code) => Err(format!(
-                "clang terminated unsuccessfully with code {}.",
-                code
-            )),
-            None => Err("clang terminated unsuccessfully".to_owned()),
-        }
-    }
+    Ok(io::BufWriter::new(output))
 }
@@ -1 +1,6 @@
 mod test_bf;
+mod test_debugger;
+mod test_driver;
+mod test_interpreter;
+mod test_jit;
+mod test_trace;
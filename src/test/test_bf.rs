@@ -6,10 +6,10 @@ fn optimize_no_change() {
 
     let assert_no_change = |v: Vec<_>| assert_optimize(v.clone(), v);
 
-    assert_no_change(vec![SetValue(5)]);
-    assert_no_change(vec![AddValue(5)]);
-    assert_no_change(vec![Input]);
-    assert_no_change(vec![Output]);
+    assert_no_change(vec![SetValue(0, 5)]);
+    assert_no_change(vec![AddValue(0, 5)]);
+    assert_no_change(vec![Input(0)]);
+    assert_no_change(vec![Output(0)]);
     assert_no_change(vec![BeginLoop]);
     assert_no_change(vec![EndLoop]);
 
@@ -17,22 +17,289 @@ fn optimize_no_change() {
 
 #[test]
 fn optimize_add_set_value() {
-    assert_optimize(vec![AddValue(0)], vec![]);
-    assert_optimize(vec![AddValue(5), AddValue(3)], vec![AddValue(8)]);
-    assert_optimize(vec![SetValue(5), AddValue(3)], vec![SetValue(8)]);
-    assert_optimize(vec![SetValue(5), SetValue(3)], vec![SetValue(3)]);
-    assert_optimize(vec![AddValue(5), SetValue(3)], vec![SetValue(3)]);
+    assert_optimize(vec![AddValue(0, 0)], vec![]);
+    assert_optimize(vec![AddValue(0, 5), AddValue(0, 3)], vec![AddValue(0, 8)]);
+    assert_optimize(vec![SetValue(0, 5), AddValue(0, 3)], vec![SetValue(0, 8)]);
+    assert_optimize(vec![SetValue(0, 5), SetValue(0, 3)], vec![SetValue(0, 3)]);
+    assert_optimize(vec![AddValue(0, 5), SetValue(0, 3)], vec![SetValue(0, 3)]);
+    assert_optimize(vec![AddValue(1, 5), AddValue(1, 3)], vec![AddValue(1, 8)]);
+
+    let assert_no_change = |v: Vec<_>| assert_optimize(v.clone(), v);
+    assert_no_change(vec![AddValue(0, 5), AddValue(1, 3)]);
+    assert_no_change(vec![SetValue(0, 5), AddValue(1, 3)]);
+    assert_no_change(vec![AddValue(0, 5), SetValue(1, 3)]);
 }
 
 #[test]
 fn optimize_loop() {
-    assert_optimize(vec![EndLoop, AddValue(5)], vec![EndLoop, SetValue(5)]);
-    assert_optimize(vec![EndLoop, SetValue(0)], vec![EndLoop]);
-    assert_optimize(vec![SetValue(0), BeginLoop], vec![SetValue(0)]);
+    assert_optimize(vec![EndLoop, AddValue(0, 5)], vec![EndLoop, SetValue(0, 5)]);
+    assert_optimize(vec![EndLoop, SetValue(0, 0)], vec![EndLoop]);
+    assert_optimize(vec![SetValue(0, 0), BeginLoop], vec![SetValue(0, 0)]);
     assert_optimize(vec![EndLoop, BeginLoop], vec![EndLoop]);
 }
 
+#[test]
+fn optimize_scan_loop() {
+    assert_optimize(vec![BeginLoop, AddPointer(1), EndLoop], vec![ScanZero(1)]);
+    assert_optimize(
+        vec![BeginLoop, AddPointer(-1), AddPointer(-1), EndLoop, AddValue(0, 3)],
+        vec![ScanZero(-2), SetValue(0, 3)],
+    );
+    assert_optimize(vec![ScanZero(1), SetValue(0, 0)], vec![ScanZero(1)]);
+    assert_optimize(vec![ScanZero(1), BeginLoop, Output(0), EndLoop], vec![ScanZero(1)]);
+}
+
+#[test]
+fn optimize_mul_add_loop() {
+    let mul_add = |offset, factor| MulAdd { offset, factor };
+
+    assert_optimize(
+        vec![BeginLoop, AddValue(0, -1), AddPointer(1), AddValue(0, 1), AddPointer(1),
+             AddValue(0, 2), AddPointer(-2), EndLoop],
+        vec![mul_add(1, 1), mul_add(2, 2), SetValue(0, 0)],
+    );
+    assert_optimize(
+        vec![BeginLoop, AddPointer(-1), AddValue(0, 3), AddPointer(1), AddValue(0, -1), EndLoop],
+        vec![mul_add(-1, 3), SetValue(0, 0)],
+    );
+    assert_optimize(
+        vec![AddValue(0, 5), BeginLoop, AddValue(0, -1), AddPointer(1), AddValue(0, -1),
+             AddPointer(-1), EndLoop, AddValue(0, 2)],
+        vec![AddValue(0, 5), mul_add(1, -1), SetValue(0, 2)],
+    );
+
+    // not balanced, no single decrement or not only additions
+    let assert_no_change = |v: Vec<_>| assert_optimize(v.clone(), v);
+    assert_no_change(vec![BeginLoop, AddValue(0, -1), AddPointer(1), EndLoop]);
+    assert_no_change(vec![BeginLoop, AddValue(0, -2), AddPointer(1), AddValue(0, 1),
+                          AddPointer(-1), EndLoop]);
+    assert_no_change(vec![BeginLoop, AddValue(0, -1), AddPointer(1), Output(0), AddPointer(-1),
+                          EndLoop]);
+
+    // only the inner loop is rewritten
+    assert_pass(
+        "mul-add-loop",
+        vec![BeginLoop, AddPointer(1), BeginLoop, AddValue(0, -1), AddValue(1, 2), EndLoop,
+             AddPointer(-1), AddValue(0, -1), EndLoop],
+        vec![BeginLoop, AddPointer(1), mul_add(1, 2), SetValue(0, 0), AddPointer(-1),
+             AddValue(0, -1), EndLoop],
+    );
+}
+
+#[test]
+fn sink_pointer_moves() {
+    let assert_sink = |input, expected| assert_pass("sink-pointer", input, expected);
+
+    assert_sink(
+        vec![AddPointer(1), AddValue(0, 1), AddPointer(1), AddValue(0, 1), AddPointer(-2)],
+        vec![AddValue(1, 1), AddValue(2, 1)],
+    );
+    assert_sink(
+        vec![AddPointer(2), Input(0), AddPointer(-1), Output(0), SetValue(0, 3)],
+        vec![Input(2), Output(1), SetValue(1, 3), AddPointer(1)],
+    );
+    assert_sink(
+        vec![AddPointer(1), BeginLoop, AddPointer(-1), AddValue(0, 1), AddPointer(2), EndLoop],
+        vec![AddPointer(1), BeginLoop, AddValue(-1, 1), AddPointer(1), EndLoop],
+    );
+}
+
+#[test]
+fn single_passes() {
+    let input = vec![AddValue(0, 1), AddValue(0, 1), AddPointer(1), AddPointer(1), BeginLoop,
+                     AddValue(0, -1), EndLoop, BeginLoop, AddPointer(1), EndLoop];
+
+    assert_pass("fold-values", input.clone(),
+                vec![AddValue(0, 2), AddPointer(1), AddPointer(1), BeginLoop, AddValue(0, -1),
+                     EndLoop, BeginLoop, AddPointer(1), EndLoop]);
+    assert_pass("fold-pointer", input.clone(),
+                vec![AddValue(0, 1), AddValue(0, 1), AddPointer(2), BeginLoop, AddValue(0, -1),
+                     EndLoop, BeginLoop, AddPointer(1), EndLoop]);
+    assert_pass("clear-loop", input.clone(),
+                vec![AddValue(0, 1), AddValue(0, 1), AddPointer(1), AddPointer(1), SetValue(0, 0),
+                     BeginLoop, AddPointer(1), EndLoop]);
+    assert_pass("scan-loop", input.clone(),
+                vec![AddValue(0, 1), AddValue(0, 1), AddPointer(1), AddPointer(1), BeginLoop,
+                     AddValue(0, -1), EndLoop, ScanZero(1)]);
+    assert_pass("dead-loop", input.clone(),
+                vec![AddValue(0, 1), AddValue(0, 1), AddPointer(1), AddPointer(1), BeginLoop,
+                     AddValue(0, -1), EndLoop]);
+}
+
+#[test]
+fn pass_manager() {
+    let input = vec![AddValue(0, 1), AddValue(0, 1), BeginLoop, AddValue(0, -1), EndLoop,
+                     BeginLoop, AddPointer(1), EndLoop];
+
+    let optimize = |manager: PassManager| {
+        let mut insns = InstructionList::from_vec(input.clone()).unwrap();
+        manager.run(&mut insns);
+        insns.list
+    };
+
+    assert_eq!(optimize(PassManager::with_opt_level(0)), input);
+    assert_eq!(optimize(PassManager::with_opt_level(3)), vec![SetValue(0, 0), ScanZero(1)]);
+    assert_eq!(optimize(PassManager::with_pass_names(&["fold-values", "clear-loop"]).unwrap()),
+               vec![SetValue(0, 0), BeginLoop, AddPointer(1), EndLoop]);
+
+    assert_eq!(PassManager::with_pass_names(&["fold-values", "unknown"]).err(),
+               Some("unknown pass 'unknown'".to_owned()));
+    assert_eq!(PassManager::with_opt_level(3).pass_names(), PASS_NAMES.to_vec());
+}
+
+#[test]
+fn parse_balanced_brackets() {
+    let insns = InstructionList::from_chars("+[>[-]<-]".chars()).unwrap();
+    assert_eq!(insns.list, vec![AddValue(0, 1), BeginLoop, AddPointer(1), BeginLoop,
+                                AddValue(0, -1), EndLoop, AddPointer(-1), AddValue(0, -1),
+                                EndLoop]);
+}
+
+#[test]
+fn parse_unmatched_brackets() {
+    let error = InstructionList::from_chars("+[\n]]\n [[-]".chars()).err().unwrap();
+    let positions: Vec<_> = error
+        .unmatched_brackets
+        .iter()
+        .map(|b| (b.bracket, b.position.line, b.position.column))
+        .collect();
+    assert_eq!(positions, vec![(']', 2, 2), ('[', 3, 2)]);
+}
+
+#[test]
+fn unbalanced_instruction_list() {
+    let error = |list| InstructionList::from_vec(list).err();
+    assert_eq!(error(vec![AddValue(0, 1), EndLoop]), Some(UnbalancedLoops));
+    assert_eq!(error(vec![BeginLoop, BeginLoop, EndLoop]), Some(UnbalancedLoops));
+    assert!(InstructionList::from_vec(vec![BeginLoop, EndLoop]).is_ok());
+}
+
+#[test]
+fn source_positions() {
+    let positions = |insns: &InstructionList| -> Vec<_> {
+        (0..insns.list.len())
+            .map(|i| insns.position(i).map(|p| (p.line, p.column)))
+            .collect()
+    };
+
+    let mut insns = InstructionList::from_chars("+ [\n-]".chars()).unwrap();
+    assert_eq!(positions(&insns), vec![Some((1, 1)), Some((1, 3)), Some((2, 1)), Some((2, 2))]);
+    insns.insert_debug_logs(DebugPlacement::All);
+    assert_eq!(insns.list.len(), 9);
+    assert_eq!(insns.position(0), insns.position(1));
+    assert_eq!(insns.position(8), insns.position(7));
+
+    let insns = InstructionList::from_ir("add 1\n  loop { out }").unwrap();
+    assert_eq!(positions(&insns), vec![Some((1, 1)), Some((2, 3)), Some((2, 10)), Some((2, 14))]);
+
+    let mut insns = InstructionList::from_chars("+.".chars()).unwrap();
+    PassManager::with_opt_level(3).run(&mut insns);
+    assert_eq!(positions(&insns), vec![Some((1, 1)), Some((1, 2))]);
+    let mut insns = InstructionList::from_chars("++.".chars()).unwrap();
+    PassManager::with_opt_level(3).run(&mut insns);
+    assert_eq!(positions(&insns), vec![Some((1, 1)), Some((1, 3))]);
+
+    let mut insns = InstructionList::from_chars("+>\n [-]>+<<\n,>>[>]".chars()).unwrap();
+    PassManager::with_opt_level(3).run(&mut insns);
+    assert_eq!(insns.to_string().split_whitespace().collect::<Vec<_>>().join(" "),
+               "add 1 set @1 0 add @2 1 in ptr 2 scan 1");
+    assert_eq!(
+        positions(&insns),
+        vec![Some((1, 1)), Some((2, 2)), Some((2, 6)), Some((3, 1)), Some((3, 4)), Some((3, 4))]
+    );
+
+    let mut insns = InstructionList::from_vec(vec![AddValue(0, 1), AddValue(0, 1)]).unwrap();
+    PassManager::with_opt_level(3).run(&mut insns);
+    assert_eq!(positions(&insns), vec![None]);
+}
+
+#[test]
+fn debug_placement() {
+    let debug_logs = |placement: DebugPlacement| {
+        let mut insns = InstructionList::from_chars_with_breakpoints("+[,.#]".chars()).unwrap();
+        insns.insert_debug_logs(placement);
+        assert_eq!(insns.positions.len(), insns.list.len());
+        insns.to_string().split_whitespace().collect::<Vec<_>>().join(" ")
+    };
+
+    assert_eq!(debug_logs(DebugPlacement::All),
+               "debug add 1 debug loop { debug in debug out debug } debug");
+    assert_eq!(debug_logs(DebugPlacement::Loops), "add 1 debug loop { debug in out debug }");
+    assert_eq!(debug_logs(DebugPlacement::Io), "add 1 loop { in debug out debug }");
+    assert_eq!(debug_logs(DebugPlacement::Breakpoints), "add 1 loop { in out debug }");
+
+    assert_eq!(InstructionList::from_chars("+#".chars()).unwrap().list, vec![AddValue(0, 1)]);
+}
+
+#[test]
+fn tree_conversion() {
+    let list = vec![AddValue(0, 1), BeginLoop, AddPointer(1), BeginLoop, EndLoop, Output(0),
+                    EndLoop, BeginLoop, EndLoop];
+    let nodes = Node::from_insns(&list).unwrap();
+
+    assert_eq!(nodes, vec![
+        Node::Insn(AddValue(0, 1)),
+        Node::Loop(vec![Node::Insn(AddPointer(1)), Node::Loop(vec![]), Node::Insn(Output(0))]),
+        Node::Loop(vec![]),
+    ]);
+    assert_eq!(Node::to_insns(&nodes), list);
+
+    assert_eq!(Node::from_insns(&[BeginLoop, BeginLoop, EndLoop]), None);
+    assert_eq!(Node::from_insns(&[BeginLoop, EndLoop, EndLoop]), None);
+    assert_eq!(Node::from_insns(&[]), Some(vec![]));
+}
+
+#[test]
+fn ir_round_trip() {
+    let list = vec![SetValue(0, 0), SetValue(-2, 5), AddValue(3, -1), AddPointer(-4),
+                    BeginLoop, MulAdd { offset: 1, factor: -2 }, BeginLoop, ScanZero(2),
+                    Input(0), Input(1), Output(0), Output(-1), DebugLog, EndLoop, EndLoop];
+    let text = InstructionList::from_vec(list.clone()).unwrap().to_string();
+
+    assert_eq!(text, "set 0\nset @-2 5\nadd @3 -1\nptr -4\nloop {\n    muladd @1 -2\n    \
+                      loop {\n        scan 2\n        in\n        in @1\n        out\n        \
+                      out @-1\n        debug\n    }\n}\n");
+    assert_eq!(InstructionList::from_ir(&text).unwrap().list, list);
+
+    let compact = "# comment\nadd +3 loop{ptr 1}out @2 # trailing comment";
+    assert_eq!(InstructionList::from_ir(compact).unwrap().list,
+               vec![AddValue(0, 3), BeginLoop, AddPointer(1), EndLoop, Output(2)]);
+}
+
+#[test]
+fn ir_parse_errors() {
+    let assert_error = |text: &str, message: &str, line: usize, column: usize| {
+        let error = InstructionList::from_ir(text).err().unwrap();
+        assert_eq!(error.message, message);
+        assert_eq!(error.position, SourcePosition { line, column });
+    };
+
+    assert_error("add 1\n  mul 2", "unknown instruction 'mul'", 2, 3);
+    assert_error("add", "expected integer after 'add'", 1, 1);
+    assert_error("ptr x", "invalid integer 'x'", 1, 5);
+    assert_error("out @", "invalid offset '@'", 1, 5);
+    assert_error("loop out", "expected '{' after 'loop'", 1, 1);
+    assert_error("loop {\n loop {\n }", "unclosed 'loop'", 1, 1);
+    assert_error("loop { }\n}", "unmatched '}'", 2, 1);
+}
+
+/// Optimizes `input`; unlike `InstructionList::from_vec` this allows fragments with unbalanced
+/// loops, which the passes have to leave intact.
 fn assert_optimize(input: Vec<BfInstruction>, expected: Vec<BfInstruction>) {
-    let actual = InstructionList::from_vec(input).list;
-    assert_eq!(actual, expected);
+    let mut insns = fragment(input);
+    PassManager::with_opt_level(2).run(&mut insns);
+    assert_eq!(insns.list, expected);
+}
+
+fn assert_pass(name: &str, input: Vec<BfInstruction>, expected: Vec<BfInstruction>) {
+    let mut actual = fragment(input);
+    create_pass(name).unwrap().run(&mut actual);
+    assert_eq!(actual.list, expected);
+}
+
+fn fragment(list: Vec<BfInstruction>) -> InstructionList {
+    InstructionList {
+        list,
+        positions: Vec::new(),
+    }
 }
@@ -0,0 +1,97 @@
+use bf::*;
+use debugger::Debugger;
+use driver::Compiler;
+use llvm::OptLevel;
+
+const SOURCE: &str = "++[>+++<-]\n>.,.";
+
+/// Runs the debugger with `commands`; returns the console, the program output, the program
+/// counter and the pointer at the end of the session.
+fn debug(machine: &BfMachine, commands: &str) -> (String, String, usize, u32) {
+    debug_source(machine, SOURCE, commands)
+}
+
+fn debug_source(machine: &BfMachine, source: &str, commands: &str) -> (String, String, usize, u32) {
+    let mut console = Vec::new();
+    let mut output = Vec::new();
+    let (pc, pointer) = {
+        let mut debugger =
+            Debugger::new(machine, source, commands.as_bytes(), &mut console, &mut output);
+        debugger.run().unwrap();
+        (debugger.interpreter().pc(), debugger.interpreter().pointer())
+    };
+    (
+        String::from_utf8(console).unwrap(),
+        String::from_utf8(output).unwrap(),
+        pc,
+        pointer,
+    )
+}
+
+fn machine() -> BfMachine {
+    Compiler::new().opt_level(OptLevel::O0).parse(SOURCE).unwrap()
+}
+
+#[test]
+fn debugger_steps() {
+    let machine = machine();
+
+    let (console, _, pc, _) = debug(&machine, "s\ns 2\n\n");
+    assert_eq!(pc, 5);
+    assert!(console.starts_with("@1 at 1:1: add 1    [0] = 0\n 1 | ++[>+++<-]\n   | ^\n"));
+
+    // NOTE(jpg): the loop runs twice, leaving six in cell 1
+    let (console, _, pc, pointer) = debug(&machine, "u @3\nn\nt 1\n");
+    assert_eq!((pc, pointer), (10, 0));
+    assert!(console.contains("[0:0] 1:6\n"));
+
+    let (console, _, pc, _) = debug(&machine, "u 2:2\n");
+    assert_eq!(pc, 11);
+    assert!(console.contains(" 2 | >.,.\n   |  ^\n"));
+}
+
+#[test]
+fn debugger_breakpoints_and_watches() {
+    let machine = machine();
+
+    let (console, _, pc, _) = debug(&machine, "b 1:4\nb @10\nc\nd 1\nc\ni\n");
+    assert!(console.contains("breakpoint 1 at @4 at 1:4: ptr 1\n"));
+    assert!(console.contains("breakpoint 2 at @10 at 1:10: }\n"));
+    assert_eq!(pc, 9);
+
+    let (console, _, pc, _) = debug(&machine, "w 1\nc\nc\n");
+    assert!(console.contains("watching cell 1 = 0\n"));
+    assert!(console.contains("cell 1: 1 -> 2\n"));
+    assert_eq!(pc, 6);
+
+    let (console, _, _, _) = debug(&machine, "b 3\nb @99\nb x\n");
+    assert!(console.contains("no instruction at 3\n"));
+    assert!(console.contains("no instruction @99\n"));
+    assert!(console.contains("invalid location 'x'"));
+}
+
+#[test]
+fn debugger_input_and_termination() {
+    let machine = machine();
+
+    let (console, output, _, _) = debug(&machine, "c\nA\nc\ns\nr\nq\ns\n");
+    assert_eq!(output, "\u{6}A");
+    assert!(console.contains("input> "));
+    assert!(console.contains("program terminated with result 65\n"));
+    assert!(console.contains("the program is not running; use 'restart'\n"));
+    assert!(console.ends_with("(bf) "));
+}
+
+#[test]
+fn debugger_empty_program() {
+    for &source in &["", "empty\n"] {
+        let machine = Compiler::new().opt_level(OptLevel::O0).parse(source).unwrap();
+
+        let (console, output, pc, _) = debug_source(&machine, source, "b 1\nl\ns\ns\n");
+        assert!(console.starts_with("@1: end of program, no instructions left    [0] = 0\n"));
+        assert!(console.contains("no instruction at 1\n"));
+        assert!(console.contains("program terminated with result 0\n"));
+        assert!(console.contains("the program is not running; use 'restart'\n"));
+        assert_eq!((output.as_str(), pc), ("", 0));
+    }
+}
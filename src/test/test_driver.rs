@@ -0,0 +1,178 @@
+use bf::*;
+use driver::*;
+use error::CompileError;
+use llvm::{OptLevel, Target};
+
+use super::test_interpreter::HELLO_WORLD;
+
+#[test]
+fn compile_and_run() {
+    let compiler = Compiler::new().memory_size(16);
+    let program = compiler.compile(&compiler.parse(HELLO_WORLD).unwrap()).unwrap();
+    let result = compiler.run(&program, b"").unwrap();
+
+    assert_eq!(result.output, b"Hello World!\n");
+    assert_eq!(result.exit_code, 10);
+
+    let compiler = Compiler::new()
+        .cell_bits(16)
+        .memory_overflow(MemoryOverflowBehaviour::Abort)
+        .eof(EofBehaviour::Zero)
+        .opt_level(OptLevel::O0);
+    let run = |ir| {
+        let program = compiler.compile(&compiler.parse_ir(ir).unwrap()).unwrap();
+        compiler.run(&program, b"").unwrap().exit_code
+    };
+    assert_eq!(run("add 7\nin\nadd 300"), 300);
+    assert_eq!(run("add 300\nptr -1"), -1);
+}
+
+#[test]
+fn emit_files() {
+    let compiler = Compiler::new();
+    let program = compiler.compile(&compiler.parse("+.").unwrap()).unwrap();
+
+    let mut assembly = Vec::new();
+    compiler.emit(&program, FileType::Assembly, &mut assembly).unwrap();
+    assert!(String::from_utf8(assembly).unwrap().contains("brainfuck"));
+
+    let mut bitcode = Vec::new();
+    compiler.emit(&program, FileType::Bitcode, &mut bitcode).unwrap();
+    assert!(bitcode.starts_with(b"BC"));
+}
+
+#[test]
+fn opt_levels() {
+    let assembly_lines = |opt_level: &str| {
+        let compiler = Compiler::new().opt_level(opt_level.parse().ok().unwrap());
+        let program = compiler.compile(&compiler.parse(HELLO_WORLD).unwrap()).unwrap();
+        assert_eq!(compiler.run(&program, b"").unwrap().output, b"Hello World!\n");
+
+        let mut assembly = Vec::new();
+        compiler.emit(&program, FileType::Assembly, &mut assembly).unwrap();
+        String::from_utf8(assembly).unwrap().lines().count()
+    };
+
+    let o0 = assembly_lines("0");
+    for opt_level in &["1", "2", "3", "s", "z"] {
+        assert!(assembly_lines(opt_level) < o0);
+    }
+    assert!("4".parse::<OptLevel>().is_err());
+}
+
+#[test]
+fn debug_info() {
+    let compiler = Compiler::new().opt_level(OptLevel::O0).debug_info("/src/test.bf");
+    let program = compiler.compile(&compiler.parse("+\n [-]").unwrap()).unwrap();
+    let ir = program.module.print_to_string();
+
+    assert!(ir.contains("!DIFile(filename: \"test.bf\", directory: \"/src\")"));
+    assert!(ir.contains("!DILocation(line: 2, column: 2"));
+    assert_eq!(compiler.run(&program, b"").unwrap().exit_code, 0);
+
+    // NOTE(jpg): optimized instructions keep the position of the first character folded into them
+    let compiler = Compiler::new().opt_level(OptLevel::O2).debug_info("/src/test.bf");
+    let program = compiler.compile(&compiler.parse("++++++[>+++++++++++<-]>.\n+.\n\n+.").unwrap());
+    let ir = program.unwrap().module.print_to_string();
+    for &(line, column) in &[(1, 24), (2, 2), (4, 2)] {
+        let location = format!("!DILocation(line: {}, column: {}", line, column);
+        assert!(ir.contains(&location), "{}", location);
+    }
+}
+
+#[test]
+fn compile_errors() {
+    let compiler = Compiler::new();
+    assert_eq!(
+        compiler.parse("+\n[[-]").err().unwrap().to_string(),
+        "error: unmatched '[' at line 2, column 1\n 2 | [[-]\n   | ^\naborting due to 1 \
+         unmatched bracket"
+    );
+    assert_eq!(
+        compiler.parse_ir("add x").err().unwrap().to_string(),
+        "error: invalid integer 'x' at line 1, column 5"
+    );
+
+    let compiler = Compiler::new().target(Target {
+        triple: Some("aarch64-unknown-linux-gnu".to_owned()),
+        ..Target::default()
+    });
+    let program = compiler.compile(&compiler.parse("+").unwrap()).unwrap();
+    assert_eq!(program.module.get_target(), Some("aarch64-unknown-linux-gnu".to_owned()));
+    assert_eq!(compiler.run(&program, b"").err().unwrap().exit_code(), 1);
+}
+
+#[test]
+fn scan_zero_library_calls() {
+    let declarations = |triple: &str| {
+        let compiler = Compiler::new()
+            .memory_overflow(MemoryOverflowBehaviour::Abort)
+            .target(Target {
+                triple: Some(triple.to_owned()),
+                ..Target::default()
+            });
+        let program = compiler.codegen(&compiler.parse_ir("scan 1\nscan -1").unwrap());
+        let ir = program.llvm_ir();
+        ir.lines().filter(|line| line.starts_with("declare")).collect::<Vec<_>>().join("\n")
+    };
+
+    let linux_x86 = declarations("i686-unknown-linux-gnu");
+    assert!(linux_x86.contains("declare i8* @memchr(i8*, i32, i32)"));
+    assert!(linux_x86.contains("declare i8* @memrchr(i8*, i32, i32)"));
+
+    // NOTE(jpg): no memrchr, the backwards scan falls back to a loop
+    let mac = declarations("x86_64-apple-darwin");
+    assert!(mac.contains("declare i8* @memchr(i8*, i32, i64)"));
+    assert!(!mac.contains("memrchr"));
+}
+
+#[test]
+fn error_exit_codes() {
+    let compiler = Compiler::new();
+    let parse_error = compiler.parse("]").err().unwrap();
+    assert!(matches!(parse_error, CompileError::Parse(_)));
+
+    let errors = [
+        CompileError::InvalidArgument("".to_owned()),
+        CompileError::io("", ::std::io::Error::other("")),
+        parse_error,
+        CompileError::Verify("".to_owned()),
+        CompileError::Codegen("".to_owned()),
+        CompileError::Link("".to_owned()),
+        CompileError::Runtime("".to_owned()),
+    ];
+    let mut codes: Vec<_> = errors.iter().map(|err| err.exit_code()).collect();
+    codes.dedup();
+    assert_eq!(codes.len(), errors.len());
+}
+
+#[test]
+fn linker_arguments() {
+    assert_eq!(LinkerFlavor::from_linker("/usr/bin/clang-14"), LinkerFlavor::Clang);
+    assert_eq!(LinkerFlavor::from_linker("aarch64-linux-gnu-gcc"), LinkerFlavor::Cc);
+    assert_eq!(LinkerFlavor::from_linker("ld.lld"), LinkerFlavor::Ld);
+    assert_eq!(LinkerFlavor::from_linker("aarch64-linux-gnu-ld"), LinkerFlavor::Ld);
+
+    let options = LinkOptions {
+        linker: None,
+        args: vec!["-lm".to_owned()],
+        static_link: true,
+    };
+    assert_eq!(
+        link_arguments(LinkerFlavor::Clang, "a.o", "a", "x86_64-pc-linux-gnu", &options),
+        ["a.o", "-o", "a", "-target", "x86_64-pc-linux-gnu", "-static", "-lm"]
+    );
+    assert_eq!(
+        link_arguments(LinkerFlavor::Cc, "a.o", "a", "x86_64-pc-linux-gnu", &options),
+        ["a.o", "-o", "a", "-static", "-lm"]
+    );
+
+    let compiler = Compiler::new().link_options(LinkOptions {
+        linker: Some("bf-linker-that-does-not-exist".to_owned()),
+        ..LinkOptions::default()
+    });
+    let program = compiler.compile(&compiler.parse("+").unwrap()).unwrap();
+    let path = ::std::env::temp_dir().join("bf-linker-test");
+    let err = compiler.write_executable(&program, path.to_str().unwrap()).err().unwrap();
+    assert!(matches!(err, CompileError::Link(_)));
+}
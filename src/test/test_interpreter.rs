@@ -0,0 +1,109 @@
+use bf::*;
+use interpreter::interpret;
+
+pub const HELLO_WORLD: &str = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+
+#[test]
+fn interpret_hello_world() {
+    let (result, output) = run(HELLO_WORLD, "", MemoryOverflowBehaviour::Undefined, 16, 8);
+    assert_eq!(output, "Hello World!\n");
+    assert_eq!(result, 10);
+}
+
+#[test]
+fn interpret_input() {
+    assert_eq!(run(",.,.,.", "abc", MemoryOverflowBehaviour::Undefined, 4, 8).1, "abc");
+    assert_eq!(run(",", "", MemoryOverflowBehaviour::Undefined, 4, 8).0, -1);
+    assert_eq!(run(",+[-.,+]", "echo", MemoryOverflowBehaviour::Undefined, 4, 8).1, "echo");
+}
+
+#[test]
+fn interpret_mul_add() {
+    let source = "++++++++++[->+++++++>++++++++++<<]>.>.";
+    let expected = (100, "Fd".to_owned());
+    assert_eq!(run(source, "", MemoryOverflowBehaviour::Abort, 4, 8), expected);
+    assert_eq!(run(source, "", MemoryOverflowBehaviour::Abort, 2, 8).0, -1);
+    assert_eq!(run("<[->+<]", "", MemoryOverflowBehaviour::Wrap, 4, 8).0, 0);
+}
+
+#[test]
+fn interpret_scan_zero() {
+    let source = ">+>+>+>>+<<<<[>]+<[<]";
+    assert_eq!(run(source, "", MemoryOverflowBehaviour::Abort, 8, 8).0, 0);
+    assert_eq!(run(source, "", MemoryOverflowBehaviour::Abort, 4, 8).0, -1);
+    assert_eq!(run("+>+>+>+<<<[>]+", "", MemoryOverflowBehaviour::Grow, 4, 8).0, 1);
+    assert_eq!(run("+>+>+>+<<<[>]+", "", MemoryOverflowBehaviour::Abort, 4, 8).0, -1);
+    assert_eq!(run("+>+>>+[>]+", "", MemoryOverflowBehaviour::Wrap, 4, 8).0, 1);
+    assert_eq!(run("+>+>>+[>]+", "", MemoryOverflowBehaviour::Abort, 4, 8).0, -1);
+}
+
+#[test]
+fn interpret_eof() {
+    let mut machine = BfMachine {
+        cache_size: 4,
+        cell_bits: 8,
+        instructions: InstructionList::from_chars("+++++,".chars()).unwrap(),
+        memory_overflow: MemoryOverflowBehaviour::Undefined,
+        eof: EofBehaviour::MinusOne,
+        trace_fd: 2,
+    };
+
+    assert_eq!(run_machine(&machine, "").0, -1);
+    assert_eq!(run_machine(&machine, "a").0, 'a' as i32);
+    machine.eof = EofBehaviour::Zero;
+    assert_eq!(run_machine(&machine, "").0, 0);
+    machine.eof = EofBehaviour::Unchanged;
+    assert_eq!(run_machine(&machine, "").0, 5);
+}
+
+#[test]
+fn interpret_memory_overflow() {
+    assert_eq!(run("<+", "", MemoryOverflowBehaviour::Abort, 4, 8).0, -1);
+    assert_eq!(run(">>>>", "", MemoryOverflowBehaviour::Abort, 4, 8).0, -1);
+    assert_eq!(run(">>>+", "", MemoryOverflowBehaviour::Abort, 4, 8).0, 1);
+    assert_eq!(run("+>>>>+", "", MemoryOverflowBehaviour::Wrap, 4, 8).0, 2);
+    assert_eq!(run("<+", "", MemoryOverflowBehaviour::Grow, 4, 8).0, -1);
+    assert_eq!(run("+>>>>>>>>>>+<<<<<<<<<<", "", MemoryOverflowBehaviour::Grow, 4, 8).0, 1);
+    assert_eq!(run(">>>>>>>>>>+", "", MemoryOverflowBehaviour::Grow, 4, 8).0, 1);
+}
+
+#[test]
+fn interpret_cell_bits() {
+    // 256 increments followed by a check for zero
+    let source = "++++++++[>++++++++++++++++++++++++++++++++<-]>[>+<[-]]>";
+    assert_eq!(run(source, "", MemoryOverflowBehaviour::Undefined, 4, 8).0, 0);
+    assert_eq!(run(source, "", MemoryOverflowBehaviour::Undefined, 4, 16).0, 1);
+    assert_eq!(run("-<", "", MemoryOverflowBehaviour::Wrap, 4, 16).0, 0);
+    assert_eq!(run("-", "", MemoryOverflowBehaviour::Undefined, 4, 64).0, -1);
+
+    // output is truncated to a byte: 321 % 256 == 'A'
+    let source = "++++++++++++++++[>++++++++++++++++++++<-]>+.";
+    assert_eq!(run(source, "", MemoryOverflowBehaviour::Undefined, 4, 32), (321, "A".to_owned()));
+}
+
+fn run(
+    source: &str,
+    input: &str,
+    memory_overflow: MemoryOverflowBehaviour,
+    cache_size: i64,
+    cell_bits: u32,
+) -> (i32, String) {
+    let mut instructions = InstructionList::from_chars(source.chars()).unwrap();
+    PassManager::with_opt_level(3).run(&mut instructions);
+
+    let machine = BfMachine {
+        cache_size,
+        cell_bits,
+        instructions,
+        memory_overflow,
+        eof: EofBehaviour::MinusOne,
+        trace_fd: 2,
+    };
+    run_machine(&machine, input)
+}
+
+fn run_machine(machine: &BfMachine, input: &str) -> (i32, String) {
+    let mut output = Vec::new();
+    let result = interpret(machine, &mut input.as_bytes(), &mut output).unwrap();
+    (result, String::from_utf8(output).unwrap())
+}
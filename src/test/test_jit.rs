@@ -0,0 +1,114 @@
+use bf::*;
+use bf::MemoryOverflowBehaviour::*;
+use compiler::compile;
+use driver::Compiler;
+use interpreter::interpret;
+use jit;
+use llvm::Target;
+
+use super::test_interpreter::HELLO_WORLD;
+
+#[test]
+fn jit_hello_world() {
+    let machine = Compiler::new().memory_size(16).parse(HELLO_WORLD).unwrap();
+    let (module, function_name) = compile(&machine, false, &Target::default(), None);
+    let result = jit::run(&module, function_name, b"").unwrap();
+
+    assert_eq!(result.output, b"Hello World!\n");
+    assert_eq!(result.exit_code, 10);
+}
+
+#[test]
+fn jit_callbacks() {
+    let machine = Compiler::new().memory_size(4).parse(",+[-.,+]").unwrap();
+    let (module, function_name) = compile(&machine, false, &Target::default(), None);
+
+    let mut input = "abc".bytes();
+    let mut output = String::new();
+    let result = jit::run_with_callbacks(
+        &module,
+        function_name,
+        || input.next().map_or(-1, |chr| chr as i32),
+        |chr| output.push(chr.to_ascii_uppercase() as char),
+    );
+
+    assert_eq!(result, Ok(0));
+    assert_eq!(output, "ABC");
+}
+
+#[test]
+fn jit_matches_interpreter() {
+    let cases = [
+        (",.,.,.", "abc", Undefined, 4, 8),
+        (",+[-.,+]", "echo", Undefined, 4, 8),
+        ("++++++++++[->+++++++>++++++++++<<]>.>.", "", Abort, 4, 8),
+        ("++++++++++[->+++++++>++++++++++<<]>.>.", "", Abort, 2, 8),
+        (">+>+>+>>+<<<<[>]+<[<]", "", Abort, 8, 8),
+        ("+>+>+>+<<<[>]+", "", Grow, 4, 8),
+        ("+>+>>+[>]+", "", Wrap, 4, 8),
+        ("+>>>>+", "", Wrap, 4, 8),
+        ("<+", "", Grow, 4, 8),
+        ("+>>>>>>>>>>+<<<<<<<<<<", "", Grow, 4, 8),
+        ("-<", "", Wrap, 4, 16),
+        ("++++++++++++++++[>++++++++++++++++++++<-]>+.", "", Undefined, 4, 32),
+        ("-", "", Undefined, 4, 64),
+    ];
+
+    for &(source, input, memory_overflow, cache_size, cell_bits) in &cases {
+        let compiler = Compiler::new()
+            .memory_size(cache_size)
+            .cell_bits(cell_bits)
+            .memory_overflow(memory_overflow);
+        let machine = compiler.parse(source).unwrap();
+
+        let mut expected = Vec::new();
+        let exit_code = interpret(&machine, &mut input.as_bytes(), &mut expected).unwrap();
+
+        let (module, function_name) = compile(&machine, false, &Target::default(), None);
+        let result = jit::run(&module, function_name, input.as_bytes()).unwrap();
+
+        assert_eq!((result.exit_code, result.output), (exit_code, expected), "{}", source);
+    }
+}
+
+#[test]
+fn jit_matches_interpreter_when_growing_moves_memory() {
+    // NOTE(jpg): growing from 2 to 10000 cells makes realloc move the memory; the input is
+    // written through the pointer of the current cell and read back after the pointer moved
+    let cases = [
+        "add 2\nmuladd @5000 1\nset 0\nin\nptr 1\nptr -1\nout",
+        "add @5000 1\nin\nptr 1\nptr -1\nout",
+        "in @5000\nin\nptr 1\nptr -1\nout @5000\nout",
+    ];
+
+    for source in &cases {
+        let compiler = Compiler::new()
+            .memory_size(2)
+            .memory_overflow(Grow)
+            .pass_manager(PassManager::new());
+        let machine = compiler.parse_ir(source).unwrap();
+
+        let mut expected = Vec::new();
+        let exit_code = interpret(&machine, &mut &b"AB"[..], &mut expected).unwrap();
+
+        let (module, function_name) = compile(&machine, false, &Target::default(), None);
+        let result = jit::run(&module, function_name, b"AB").unwrap();
+
+        assert_eq!((result.exit_code, result.output), (exit_code, expected), "{}", source);
+    }
+}
+
+#[test]
+fn jit_matches_interpreter_beyond_grow_limit() {
+    // NOTE(jpg): doubling the index would overflow the 32 bit size of the memory in bytes
+    let cases = [("ptr 1073741824\nadd 1", 8), ("add @134217728 1", 64)];
+
+    for &(source, cell_bits) in &cases {
+        let compiler = Compiler::new().memory_size(2).cell_bits(cell_bits).memory_overflow(Grow);
+        let machine = compiler.parse_ir(source).unwrap();
+        assert_eq!(interpret(&machine, &mut &b""[..], &mut Vec::new()).unwrap(), -1);
+
+        let program = compiler.compile(&machine).unwrap();
+        assert_eq!(compiler.run(&program, b"").unwrap().exit_code, -1, "{}", source);
+    }
+}
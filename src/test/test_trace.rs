@@ -0,0 +1,100 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::io::AsRawFd;
+
+use tempfile::tempfile;
+
+use bf::*;
+use driver::Compiler;
+use interpreter::interpret_with_trace;
+use trace::*;
+
+#[test]
+fn trace_record_json() {
+    let record = TraceRecord {
+        insn: 12,
+        pointer: -1,
+        position: Some(SourcePosition { line: 3, column: 5 }),
+        cells: vec![(1, 65), (2, -1)],
+    };
+    let json = record.to_string();
+    assert_eq!(json, r#"{"insn":12,"ptr":-1,"line":3,"column":5,"cells":[[1,65],[2,-1]]}"#);
+    assert_eq!(TraceRecord::from_json(&json), Ok(record));
+
+    let record = TraceRecord::from_json(r#"{"insn":1,"ptr":0,"cells":[]}"#).unwrap();
+    assert_eq!((record.position, record.cells), (None, vec![]));
+
+    assert!(TraceRecord::from_json(r#"{"insn":1,"ptr":0}"#).is_err());
+    assert!(TraceRecord::from_json(r#"{"insn":1,"ptr":0,"cells":[[1]]}"#).is_err());
+    assert!(TraceRecord::from_json(r#"{"insn":1,"ptr":0,"cells":[]} x"#).is_err());
+
+    let mut state = TraceState::new();
+    assert_eq!(state.changed_cells(&[0, 3]), vec![(1, 3)]);
+    assert_eq!(state.changed_cells(&[0, 3, 0, 7]), vec![(3, 7)]);
+}
+
+#[test]
+fn jit_trace_matches_interpreter() {
+    let source = "++>+++[<+>-]<.";
+    let compiler = Compiler::new()
+        .opt_level(::llvm::OptLevel::O0)
+        .memory_size(2)
+        .memory_overflow(MemoryOverflowBehaviour::Grow)
+        .emit_debug(true);
+
+    let mut expected = Vec::new();
+    let machine = compiler.parse(source).unwrap();
+    interpret_with_trace(&machine, &mut "".as_bytes(), &mut Vec::new(), &mut expected).unwrap();
+
+    let mut file = tempfile().unwrap();
+    let compiler = compiler.trace_fd(file.as_raw_fd());
+    let program = compiler.compile(&compiler.parse(source).unwrap()).unwrap();
+    assert_eq!(compiler.run(&program, b"").unwrap().output, b"\x05");
+
+    let mut trace = String::new();
+    file.seek(SeekFrom::Start(0)).unwrap();
+    file.read_to_string(&mut trace).unwrap();
+    assert_eq!(trace, String::from_utf8(expected).unwrap());
+
+    let records: Vec<_> = trace.lines().map(|line| TraceRecord::from_json(line).unwrap()).collect();
+    assert_eq!(records[0].position, Some(SourcePosition { line: 1, column: 1 }));
+    assert_eq!(records[1].cells, vec![(0, 1)]);
+}
+
+#[test]
+fn optimized_trace_keeps_source_positions() {
+    let compiler = Compiler::new().emit_debug(true).debug_at(DebugPlacement::Io);
+    let machine = compiler.parse("++++++++\n[->++++++++<]>\n+.").unwrap();
+
+    let mut trace = Vec::new();
+    interpret_with_trace(&machine, &mut "".as_bytes(), &mut Vec::new(), &mut trace).unwrap();
+
+    let trace = String::from_utf8(trace).unwrap();
+    let records: Vec<_> = trace.lines().map(|line| TraceRecord::from_json(line).unwrap()).collect();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].position, Some(SourcePosition { line: 3, column: 2 }));
+    assert_eq!(records[0].pointer, 1);
+    assert_eq!(records[0].cells, vec![(1, 65)]);
+}
+
+#[test]
+fn optimized_trace_follows_source_code() {
+    let trace = |source, debug_at| {
+        let compiler = Compiler::new().emit_debug(true).debug_at(debug_at);
+        let machine = compiler.parse(source).unwrap();
+        let mut trace = Vec::new();
+        interpret_with_trace(&machine, &mut "".as_bytes(), &mut Vec::new(), &mut trace).unwrap();
+        let trace = String::from_utf8(trace).unwrap();
+        trace.lines().map(|line| TraceRecord::from_json(line).unwrap()).collect::<Vec<_>>()
+    };
+
+    // NOTE(jpg): sink-pointer must not move the pointer of a record into the offsets
+    let records = trace(">+>++.", DebugPlacement::Io);
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].position, Some(SourcePosition { line: 1, column: 6 }));
+    assert_eq!(records[0].pointer, 2);
+
+    // NOTE(jpg): loops stay loops instead of being rewritten to `SetValue`, `MulAdd` or `ScanZero`
+    let records = trace("++[-]>[>]++[->+<]", DebugPlacement::Loops);
+    let columns: Vec<_> = records.iter().map(|record| record.position.unwrap().column).collect();
+    assert_eq!(columns, vec![3, 3, 3, 7, 12, 12, 12]);
+}
@@ -0,0 +1,220 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::iter::Peekable;
+use std::os::raw::{c_int, c_void};
+use std::str::Chars;
+
+use bf::SourcePosition;
+
+/// Record written by a `DebugLog` instruction, one JSON object per line:
+///
+/// ```text
+/// {"insn":12,"ptr":1,"line":3,"column":5,"cells":[[1,65],[2,-1]]}
+/// ```
+///
+/// `cells` only contains the cells changed since the previous record as `[index, value]`, the
+/// tape starts out with all cells set to zero. `line` and `column` are missing if the source
+/// position is unknown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// 1-based index of the `DebugLog` instruction that wrote the record
+    pub insn: u32,
+    pub pointer: i32,
+    pub position: Option<SourcePosition>,
+    pub cells: Vec<(u32, i64)>,
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // NOTE(jpg): keep in sync with the format strings of `compiler::compile`
+        write!(f, "{{\"insn\":{},\"ptr\":{},", self.insn, self.pointer)?;
+        if let Some(position) = self.position {
+            write!(f, "\"line\":{},\"column\":{},", position.line, position.column)?;
+        }
+        write!(f, "\"cells\":[")?;
+        for (i, &(index, value)) in self.cells.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(f, "{}[{},{}]", separator, index, value)?;
+        }
+        write!(f, "]}}")
+    }
+}
+
+impl TraceRecord {
+    /// Parses a single line written by the `Display` implementation.
+    pub fn from_json(line: &str) -> Result<Self, String> {
+        let mut chars = line.trim().chars().peekable();
+        let fields = match json_value(&mut chars)? {
+            Json::Object(fields) => fields,
+            _ => return Err("expected an object".to_owned()),
+        };
+        if chars.next().is_some() {
+            return Err("unexpected characters after the object".to_owned());
+        }
+
+        let number = |name: &str| match fields.iter().find(|field| field.0 == name) {
+            Some(&(_, Json::Number(value))) => Ok(Some(value)),
+            Some(_) => Err(format!("'{}' must be a number", name)),
+            None => Ok(None),
+        };
+        let required = |name: &str| {
+            number(name)?.ok_or_else(|| format!("missing field '{}'", name))
+        };
+
+        let position = match (number("line")?, number("column")?) {
+            (Some(line), Some(column)) => {
+                Some(SourcePosition {
+                    line: line as usize,
+                    column: column as usize,
+                })
+            }
+            _ => None,
+        };
+
+        let cells = match fields.iter().find(|field| field.0 == "cells") {
+            Some(&(_, Json::Array(ref cells))) => cells,
+            _ => return Err("missing array 'cells'".to_owned()),
+        };
+        let cells = cells
+            .iter()
+            .map(|cell| match *cell {
+                Json::Array(ref pair) if pair.len() == 2 => {
+                    match (&pair[0], &pair[1]) {
+                        (&Json::Number(index), &Json::Number(value)) => Ok((index as u32, value)),
+                        _ => Err("cells must be pairs of numbers".to_owned()),
+                    }
+                }
+                _ => Err("cells must be pairs of numbers".to_owned()),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(TraceRecord {
+            insn: required("insn")? as u32,
+            pointer: required("ptr")? as i32,
+            position,
+            cells,
+        })
+    }
+}
+
+/// Collects the cells changed since the previous record, see `TraceRecord::cells`.
+#[derive(Debug, Clone, Default)]
+pub struct TraceState {
+    shadow: Vec<i64>,
+}
+
+impl TraceState {
+    pub fn new() -> Self {
+        TraceState { shadow: Vec::new() }
+    }
+
+    /// Returns the changed cells of `memory` and remembers their new values.
+    pub fn changed_cells(&mut self, memory: &[i64]) -> Vec<(u32, i64)> {
+        if self.shadow.len() < memory.len() {
+            self.shadow.resize(memory.len(), 0);
+        }
+
+        let mut cells = Vec::new();
+        for (index, (&value, old_value)) in memory.iter().zip(&mut self.shadow).enumerate() {
+            if value != *old_value {
+                *old_value = value;
+                cells.push((index as u32, value));
+            }
+        }
+        cells
+    }
+}
+
+/// Unbuffered writer for a file descriptor of the process; the descriptor is not closed.
+pub struct FdWriter {
+    fd: c_int,
+}
+
+extern "C" {
+    fn write(fd: c_int, buf: *const c_void, count: usize) -> isize;
+}
+
+impl FdWriter {
+    pub fn new(fd: i32) -> Self {
+        FdWriter { fd }
+    }
+}
+
+impl Write for FdWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = unsafe { write(self.fd, buf.as_ptr() as *const c_void, buf.len()) };
+        if written < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(written as usize)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The subset of JSON written by `TraceRecord`
+enum Json {
+    Number(i64),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+fn json_value(chars: &mut Peekable<Chars>) -> Result<Json, String> {
+    match chars.next() {
+        Some('{') => {
+            let mut fields = Vec::new();
+            if chars.peek() == Some(&'}') {
+                chars.next();
+                return Ok(Json::Object(fields));
+            }
+            loop {
+                if chars.next() != Some('"') {
+                    return Err("expected a field name".to_owned());
+                }
+                let name: String = chars.by_ref().take_while(|&c| c != '"').collect();
+                if chars.next() != Some(':') {
+                    return Err(format!("expected ':' after '{}'", name));
+                }
+                fields.push((name, json_value(chars)?));
+                match chars.next() {
+                    Some(',') => {}
+                    Some('}') => return Ok(Json::Object(fields)),
+                    _ => return Err("expected ',' or '}'".to_owned()),
+                }
+            }
+        }
+        Some('[') => {
+            let mut values = Vec::new();
+            if chars.peek() == Some(&']') {
+                chars.next();
+                return Ok(Json::Array(values));
+            }
+            loop {
+                values.push(json_value(chars)?);
+                match chars.next() {
+                    Some(',') => {}
+                    Some(']') => return Ok(Json::Array(values)),
+                    _ => return Err("expected ',' or ']'".to_owned()),
+                }
+            }
+        }
+        Some(c) if c == '-' || c.is_ascii_digit() => {
+            let mut number = c.to_string();
+            while let Some(&c) = chars.peek() {
+                if !c.is_ascii_digit() {
+                    break;
+                }
+                number.push(c);
+                chars.next();
+            }
+            number.parse().map(Json::Number).map_err(|_| {
+                format!("invalid number '{}'", number)
+            })
+        }
+        Some(c) => Err(format!("unexpected character '{}'", c)),
+        None => Err("unexpected end of line".to_owned()),
+    }
+}